@@ -0,0 +1,323 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use libp2p::{Multiaddr, PeerId};
+
+use dissonance::config::{default_data_dir, Config};
+use dissonance::network::behaviours::gate::GateRule;
+use dissonance::network::behaviours::record_store::RecordStoreBackend;
+use dissonance::profile::Profile;
+
+#[derive(Debug, Parser)]
+#[command(name = "dissonance", version, about = "Peer-to-peer end-to-end encrypted chat node")]
+pub struct Cli {
+    /// Config file to load instead of `<data-dir>/config.json`.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on; repeat for several. Replaces the configured addresses.
+    #[arg(long = "listen", global = true, value_name = "MULTIADDR")]
+    pub listen: Vec<Multiaddr>,
+
+    /// `/.../p2p/<peer>` bootstrap node; repeat for several. Replaces the configured peers.
+    #[arg(long = "bootstrap", global = true, value_name = "MULTIADDR")]
+    pub bootstrap: Vec<Multiaddr>,
+
+    /// Directory holding the identity, config and stores.
+    #[arg(long, global = true, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Run as a named profile, with its own identity, config and stores inside the data directory.
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the node interactively (the default).
+    Run(RunArgs),
+    /// Inspect or manage the node identity.
+    #[command(subcommand)]
+    Identity(IdentityCommand),
+    /// Inspect or maintain the peer store.
+    #[command(subcommand)]
+    Peers(PeersCommand),
+    /// Ban or allow peers, IP ranges and addresses. A running node applies changes on its next flush.
+    #[command(subcommand)]
+    Gate(GateCommand),
+    /// List, create or delete profiles.
+    #[command(subcommand)]
+    Profile(ProfileCommand),
+    /// Connect to an address and exit once the connection is established.
+    Dial {
+        address: Multiaddr,
+    },
+    /// Send a single chat message and wait for the recipient's acknowledgement.
+    Send {
+        peer: PeerId,
+        /// Address to reach the peer at, in addition to the ones in the peer store.
+        #[arg(long, value_name = "MULTIADDR")]
+        address: Vec<Multiaddr>,
+        #[arg(required = true, num_args = 1..)]
+        text: Vec<String>,
+    },
+}
+
+#[derive(Debug, Default, Args)]
+pub struct RunArgs {
+    /// Use a throwaway identity and keep all state in memory.
+    #[arg(long)]
+    pub ephemeral: bool,
+
+    /// Keep Kademlia records in memory only.
+    #[arg(long)]
+    pub memory_records: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum IdentityCommand {
+    /// Print the peer id and public key.
+    Show,
+    /// Write the unencrypted private key to a file, or to stdout.
+    Export {
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+    },
+    /// Replace the node identity with one exported or backed up earlier.
+    Import {
+        file: PathBuf,
+        /// Overwrite an existing identity (it is kept as a backup).
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print a 24-word recovery phrase for the private key, to write down and keep offline.
+    Backup {
+        /// Print a compact checksummed code suitable for a QR code instead.
+        #[arg(long)]
+        code: bool,
+    },
+    /// Recreate the identity from a recovery phrase or backup code read from stdin.
+    Restore {
+        /// Expect a backup code instead of a phrase.
+        #[arg(long)]
+        code: bool,
+        /// Overwrite an existing identity (it is kept as a backup).
+        #[arg(long)]
+        force: bool,
+    },
+    /// Generate a new identity signed over to by the old one, which is kept as a backup.
+    Rotate,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PeersCommand {
+    /// List remembered peers.
+    List,
+    /// Forget peers not seen recently.
+    Prune {
+        /// Maximum age in seconds.
+        #[arg(long, value_name = "SECONDS", default_value_t = 7 * 24 * 60 * 60)]
+        max_age: u64,
+    },
+    /// Show the safety number to compare with a contact.
+    SafetyNumber {
+        peer: PeerId,
+    },
+    /// Mark a contact as verified after comparing safety numbers.
+    Verify {
+        peer: PeerId,
+        /// Withdraw an earlier verification instead.
+        #[arg(long)]
+        undo: bool,
+    },
+    /// Forget which peer answered at an address, after it legitimately changed hands.
+    Unpin {
+        address: Multiaddr,
+    },
+}
+
+/// Targets are a peer id, an IP address or CIDR range, or a multiaddr prefix.
+#[derive(Debug, Subcommand)]
+pub enum GateCommand {
+    /// List bans and allow entries.
+    List,
+    /// Refuse connections matching a target.
+    Ban {
+        target: GateRule,
+        /// Lift the ban automatically after this many seconds.
+        #[arg(long = "for", value_name = "SECONDS")]
+        duration: Option<u64>,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Lift a ban.
+    Unban {
+        target: GateRule,
+    },
+    /// Let a target through even if a ban matches it.
+    Allow {
+        target: GateRule,
+    },
+    /// Remove an allow entry.
+    Disallow {
+        target: GateRule,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfileCommand {
+    /// List profiles and whether they have an identity yet.
+    List,
+    /// Create a profile with a fresh identity.
+    Create {
+        name: String,
+    },
+    /// Delete a profile together with its identity and stores.
+    Delete {
+        name: String,
+        /// Confirm that the identity should be destroyed.
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+impl Cli {
+    pub fn run_args(&self) -> Option<&RunArgs> {
+        match &self.command {
+            None => Some(&DEFAULT_RUN_ARGS),
+            Some(Command::Run(args)) => Some(args),
+            Some(_) => None,
+        }
+    }
+
+    /// The directory profiles are kept in: `--data-dir` if given, the default location otherwise.
+    pub fn profiles_root(&self) -> anyhow::Result<PathBuf> {
+        match &self.data_dir {
+            Some(data_dir) => Ok(data_dir.clone()),
+            None => default_data_dir(),
+        }
+    }
+
+    /// The data directory selected by `--data-dir` and `--profile`, if either was given.
+    fn selected_data_dir(&self) -> anyhow::Result<Option<PathBuf>> {
+        match &self.profile {
+            Some(name) => Ok(Some(Profile::in_root(&self.profiles_root()?, name)?.dir)),
+            None => Ok(self.data_dir.clone()),
+        }
+    }
+
+    /// Reads the config file, then applies `DSN_*` environment variables and finally the flags.
+    pub fn load_config(&self) -> anyhow::Result<Config> {
+        let data_dir = self.selected_data_dir()?;
+        let mut config = match (&self.config, &data_dir) {
+            (Some(path), _) => Config::load_from_file(path)?,
+            (None, Some(data_dir)) => Config::load_from_dir(data_dir)?,
+            (None, None) => Config::get_config()?,
+        };
+        config.apply_env_overrides()?;
+
+        if let Some(data_dir) = data_dir {
+            config.storage.data_dir = Some(data_dir);
+        }
+        if !self.listen.is_empty() {
+            config.network.listen_addrs = self.listen.clone();
+        }
+        if !self.bootstrap.is_empty() {
+            config.bootstrap.peers = self.bootstrap.clone();
+        }
+        if let Some(args) = self.run_args() {
+            if args.ephemeral {
+                config.storage.ephemeral_peer_store = true;
+                // Several throwaway nodes can run side by side, so they only get a socket when asked to.
+                config.control.enabled &= config.control.socket_path.is_some();
+            }
+            if args.ephemeral || args.memory_records {
+                config.kademlia.record_store.backend = RecordStoreBackend::Memory;
+            }
+        }
+        Ok(config)
+    }
+}
+
+static DEFAULT_RUN_ARGS: RunArgs = RunArgs { ephemeral: false, memory_records: false };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_global_flags_after_subcommand() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path().to_str().unwrap();
+        let cli = Cli::try_parse_from(["dissonance", "run", "--ephemeral", "--listen", "/ip4/127.0.0.1/tcp/4001", "--data-dir", data_dir]).unwrap();
+        let config = cli.load_config().unwrap();
+        assert_eq!(config.network.listen_addrs, vec!["/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap()]);
+        assert_eq!(config.data_dir().unwrap(), temp.path());
+        assert!(config.storage.ephemeral_peer_store);
+        assert_eq!(config.kademlia.record_store.backend, RecordStoreBackend::Memory);
+    }
+
+    #[test]
+    fn test_profile_selects_data_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().to_str().unwrap();
+        let cli = Cli::try_parse_from(["dissonance", "--data-dir", root, "--profile", "work", "run"]).unwrap();
+        assert_eq!(cli.load_config().unwrap().data_dir().unwrap(), temp.path().join("profiles").join("work"));
+
+        let cli = Cli::try_parse_from(["dissonance", "--data-dir", root, "--profile", "default", "run"]).unwrap();
+        assert_eq!(cli.load_config().unwrap().data_dir().unwrap(), temp.path());
+
+        let cli = Cli::try_parse_from(["dissonance", "--profile", "../escape", "run"]).unwrap();
+        assert!(cli.load_config().is_err());
+    }
+
+    #[test]
+    fn test_send_joins_words() {
+        let peer = PeerId::random();
+        let cli = Cli::try_parse_from(["dissonance", "send", &peer.to_string(), "hello", "there"]).unwrap();
+        match cli.command {
+            Some(Command::Send { peer: parsed, text, .. }) => {
+                assert_eq!(parsed, peer);
+                assert_eq!(text.join(" "), "hello there");
+            }
+            other => panic!("Expected send, got {other:?}"),
+        }
+        assert!(cli_parse_fails(&["dissonance", "send", "not-a-peer", "hi"]));
+        assert!(cli_parse_fails(&["dissonance", "dial", "not-an-addr"]));
+    }
+
+    #[test]
+    fn test_gate_targets() {
+        let cli = Cli::try_parse_from(["dissonance", "gate", "ban", "10.0.0.0/8", "--for", "60"]).unwrap();
+        match cli.command {
+            Some(Command::Gate(GateCommand::Ban { target, duration, .. })) => {
+                assert_eq!(target, "10.0.0.0/8".parse().unwrap());
+                assert_eq!(duration, Some(60));
+            }
+            other => panic!("Expected gate ban, got {other:?}"),
+        }
+        assert!(cli_parse_fails(&["dissonance", "gate", "ban", "nobody"]));
+    }
+
+    fn cli_parse_fails(args: &[&str]) -> bool {
+        Cli::try_parse_from(args).is_err()
+    }
+}
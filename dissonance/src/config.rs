@@ -0,0 +1,556 @@
+use std::{fs, num::NonZeroUsize, path::{Path, PathBuf}, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+
+use crate::network::behaviours::gate::GateList;
+use crate::network::behaviours::record_store::{RecordStoreBackend, RecordStoreConfig, RECORD_STORE_FILE};
+use crate::network::bootstrap::BootstrapConfig;
+use crate::reputation::ReputationConfig;
+
+pub const CONFIG_FILE: &str = "config.json";
+pub const CONTROL_SOCKET_FILE: &str = "control.sock";
+
+/// Where the node keeps its identity, config and stores unless told otherwise.
+pub fn default_data_dir() -> Result<PathBuf> {
+    let cf_dir = dirs::config_dir().context("Could not determine config directory")?;
+    Ok(cf_dir.join("dsn-chat"))
+}
+
+/// Node configuration, loaded from `dsn-chat/config.json` and then overridden by
+/// `DSN_*` environment variables and command-line flags, in that order.
+/// Every section falls back to its defaults, so a config file only needs the keys it changes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkOptions,
+    pub bootstrap: BootstrapConfig,
+    pub kademlia: KademliaOptions,
+    pub autonat: AutonatOptions,
+    pub identify: IdentifyOptions,
+    pub mdns: MdnsOptions,
+    pub yamux: YamuxOptions,
+    pub quic: QuicOptions,
+    pub websocket: WebsocketOptions,
+    pub relay: RelayOptions,
+    pub chat: ChatOptions,
+    pub rooms: RoomsOptions,
+    pub storage: StorageOptions,
+    pub control: ControlOptions,
+    pub security: SecurityOptions,
+    pub limits: LimitsOptions,
+    pub reputation: ReputationConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkOptions {
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Publicly reachable addresses of this node, announced to peers as they are.
+    pub external_addrs: Vec<Multiaddr>,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        NetworkOptions {
+            listen_addrs: vec![
+                "/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr"),
+                "/ip4/0.0.0.0/udp/0/quic-v1".parse().expect("valid multiaddr"),
+            ],
+            external_addrs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KademliaOptions {
+    #[serde(with = "duration_secs")]
+    pub query_timeout: Duration,
+    pub replication_factor: NonZeroUsize,
+    pub max_packet_size: usize,
+    pub record_store: RecordStoreConfig,
+    pub mode: DhtMode,
+}
+
+/// Whether the node serves the DHT to other peers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DhtMode {
+    /// Serve only while some external address is confirmed, by AutoNAT or `network.external_addrs`.
+    #[default]
+    Auto,
+    Client,
+    Server,
+}
+
+/// AutoNAT asks connected peers to dial us back to find out whether we are publicly reachable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutonatOptions {
+    /// Delay before the first probe, so there are peers to ask.
+    #[serde(with = "duration_secs")]
+    pub boot_delay: Duration,
+    /// Probe interval while reachability is unknown or unsettled.
+    #[serde(with = "duration_secs")]
+    pub retry_interval: Duration,
+    /// Probe interval once reachability is settled.
+    #[serde(with = "duration_secs")]
+    pub refresh_interval: Duration,
+    /// Probes that must agree before the status is settled.
+    pub confidence_max: usize,
+    /// Ignore peers on private networks, which cannot tell whether we are reachable from the internet.
+    pub only_global_ips: bool,
+}
+
+impl Default for AutonatOptions {
+    fn default() -> Self {
+        AutonatOptions {
+            boot_delay: Duration::from_secs(15),
+            retry_interval: Duration::from_secs(90),
+            refresh_interval: Duration::from_secs(15 * 60),
+            confidence_max: 3,
+            only_global_ips: true,
+        }
+    }
+}
+
+impl Default for KademliaOptions {
+    fn default() -> Self {
+        KademliaOptions {
+            query_timeout: Duration::from_secs(20),
+            replication_factor: NonZeroUsize::new(20).expect("non-zero"),
+            max_packet_size: 16 * 1024,
+            record_store: RecordStoreConfig { backend: RecordStoreBackend::Disk, ..RecordStoreConfig::default() },
+            mode: DhtMode::Auto,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentifyOptions {
+    #[serde(with = "duration_secs")]
+    pub interval: Duration,
+}
+
+impl Default for IdentifyOptions {
+    fn default() -> Self {
+        IdentifyOptions { interval: Duration::from_secs(30) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MdnsOptions {
+    pub enabled: bool,
+    #[serde(with = "duration_secs")]
+    pub ttl: Duration,
+    #[serde(with = "duration_secs")]
+    pub query_interval: Duration,
+    pub enable_ipv6: bool,
+}
+
+impl Default for MdnsOptions {
+    fn default() -> Self {
+        let defaults = libp2p::mdns::Config::default();
+        MdnsOptions { enabled: true, ttl: defaults.ttl, query_interval: defaults.query_interval, enable_ipv6: defaults.enable_ipv6 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct YamuxOptions {
+    pub max_num_streams: usize,
+}
+
+impl Default for YamuxOptions {
+    fn default() -> Self {
+        YamuxOptions { max_num_streams: 256 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuicOptions {
+    #[serde(with = "duration_secs")]
+    pub handshake_timeout: Duration,
+    #[serde(with = "duration_secs")]
+    pub max_idle_timeout: Duration,
+    /// Must be below the idle timeout of both sides to keep quiet connections open.
+    #[serde(with = "duration_secs")]
+    pub keep_alive_interval: Duration,
+    pub max_concurrent_streams: u32,
+}
+
+impl Default for QuicOptions {
+    fn default() -> Self {
+        QuicOptions {
+            handshake_timeout: Duration::from_secs(5),
+            max_idle_timeout: Duration::from_secs(10),
+            keep_alive_interval: Duration::from_secs(5),
+            max_concurrent_streams: 256,
+        }
+    }
+}
+
+/// WebSocket listeners are opened by adding `/tcp/<port>/ws` or `/tcp/<port>/wss` listen addresses.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebsocketOptions {
+    /// PEM certificate chain served on `/wss` listeners.
+    pub tls_certificate: Option<PathBuf>,
+    /// PEM private key for `tls_certificate`.
+    pub tls_private_key: Option<PathBuf>,
+    /// PEM CA certificates trusted when dialing `/wss`, on top of the public web roots.
+    pub tls_trusted_certificates: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayOptions {
+    /// Circuit relays to reserve a slot on, as `/.../p2p/<relay id>` addresses. Peers that cannot
+    /// reach us directly dial `<relay>/p2p-circuit/p2p/<our id>` instead.
+    pub relays: Vec<Multiaddr>,
+    pub server: RelayServerOptions,
+}
+
+/// Relaying for other peers. Only useful on a node with a public address in `network.external_addrs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayServerOptions {
+    pub enabled: bool,
+    pub max_reservations: usize,
+    pub max_reservations_per_peer: usize,
+    #[serde(with = "duration_secs")]
+    pub reservation_duration: Duration,
+    pub max_circuits: usize,
+    pub max_circuits_per_peer: usize,
+    #[serde(with = "duration_secs")]
+    pub max_circuit_duration: Duration,
+    pub max_circuit_bytes: u64,
+}
+
+impl Default for RelayServerOptions {
+    fn default() -> Self {
+        RelayServerOptions {
+            enabled: false,
+            max_reservations: 128,
+            max_reservations_per_peer: 4,
+            reservation_duration: Duration::from_secs(60 * 60),
+            max_circuits: 16,
+            max_circuits_per_peer: 4,
+            max_circuit_duration: Duration::from_secs(2 * 60),
+            max_circuit_bytes: 1 << 17,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatOptions {
+    #[serde(with = "duration_secs")]
+    pub request_timeout: Duration,
+}
+
+impl Default for ChatOptions {
+    fn default() -> Self {
+        ChatOptions { request_timeout: Duration::from_secs(30) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomsOptions {
+    #[serde(with = "duration_secs")]
+    pub heartbeat_interval: Duration,
+    /// How often we announce ourselves in each joined room. Members not heard from for three
+    /// intervals are dropped and no longer sealed for.
+    #[serde(with = "duration_secs")]
+    pub announce_interval: Duration,
+}
+
+impl Default for RoomsOptions {
+    fn default() -> Self {
+        RoomsOptions { heartbeat_interval: Duration::from_secs(1), announce_interval: Duration::from_secs(30) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageOptions {
+    /// Directory for the identity and on-disk stores; `None` uses `default_data_dir`.
+    pub data_dir: Option<PathBuf>,
+    /// Keep the peer store and ban list in memory only.
+    pub ephemeral_peer_store: bool,
+    #[serde(with = "duration_secs")]
+    pub flush_interval: Duration,
+}
+
+impl Default for StorageOptions {
+    fn default() -> Self {
+        StorageOptions { data_dir: None, ephemeral_peer_store: false, flush_interval: Duration::from_secs(30) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlOptions {
+    pub enabled: bool,
+    /// Unix socket for the JSON-RPC control API; `None` puts `control.sock` in the data directory.
+    pub socket_path: Option<PathBuf>,
+}
+
+impl Default for ControlOptions {
+    fn default() -> Self {
+        ControlOptions { enabled: true, socket_path: None }
+    }
+}
+
+/// Caps that keep a single peer or a flood of them from exhausting the node; `None` means unlimited.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsOptions {
+    pub max_pending_incoming: Option<u32>,
+    pub max_pending_outgoing: Option<u32>,
+    pub max_established_incoming: Option<u32>,
+    pub max_established_outgoing: Option<u32>,
+    pub max_established_total: Option<u32>,
+    pub max_established_per_peer: Option<u32>,
+    /// Refuse new connections once the process uses this many bytes of memory.
+    pub max_memory_bytes: Option<usize>,
+    /// Same, as a fraction of physical memory; ignored when `max_memory_bytes` is set.
+    pub max_memory_fraction: Option<f64>,
+}
+
+impl Default for LimitsOptions {
+    fn default() -> Self {
+        LimitsOptions {
+            max_pending_incoming: Some(32),
+            max_pending_outgoing: Some(64),
+            max_established_incoming: Some(128),
+            max_established_outgoing: Some(128),
+            max_established_total: Some(256),
+            max_established_per_peer: Some(4),
+            max_memory_bytes: None,
+            max_memory_fraction: Some(0.9),
+        }
+    }
+}
+
+/// What to do when a dialed address is answered by, or a peer's Identify info claims, a different peer
+/// than the one pinned to it on first contact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TofuMode {
+    /// Raise a security event but keep the connection.
+    #[default]
+    Warn,
+    /// Raise a security event and disconnect.
+    Block,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityOptions {
+    pub tofu: TofuMode,
+}
+
+impl Config {
+    /// Defaults that keep all state in memory, for throwaway nodes and tests.
+    pub fn ephemeral() -> Self {
+        let mut config = Config::default();
+        config.storage.ephemeral_peer_store = true;
+        config.kademlia.record_store.backend = RecordStoreBackend::Memory;
+        config.control.enabled = false;
+        config
+    }
+
+    /// Loads the config file from the default data directory, or defaults if there is none.
+    pub fn get_config() -> Result<Self> {
+        Self::load_from_dir(&default_data_dir()?)
+    }
+
+    pub fn load_from_dir(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(CONFIG_FILE);
+        if path.exists() {
+            Self::load_from_file(&path)
+        } else {
+            Ok(Config::default())
+        }
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read config file {}", path.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    pub fn data_dir(&self) -> Result<PathBuf> {
+        match &self.storage.data_dir {
+            Some(data_dir) => Ok(data_dir.clone()),
+            None => default_data_dir(),
+        }
+    }
+
+    pub fn control_socket(&self) -> Result<PathBuf> {
+        match &self.control.socket_path {
+            Some(path) => Ok(path.clone()),
+            None => Ok(self.data_dir()?.join(CONTROL_SOCKET_FILE)),
+        }
+    }
+
+    /// The record store settings with a disk path inside the data directory unless one was given.
+    pub fn record_store(&self) -> Result<RecordStoreConfig> {
+        let mut record_store = self.kademlia.record_store.clone();
+        if record_store.backend == RecordStoreBackend::Disk && record_store.path.is_none() {
+            record_store.path = Some(self.data_dir()?.join(RECORD_STORE_FILE));
+        }
+        Ok(record_store)
+    }
+
+    /// The ban list kept in the data directory, or an empty in-memory one for an ephemeral peer store.
+    pub fn gate_list(&self) -> Result<GateList> {
+        if self.storage.ephemeral_peer_store {
+            return Ok(GateList::default());
+        }
+        GateList::open(&GateList::path(&self.data_dir()?))
+    }
+
+    pub fn apply_env_overrides(&mut self) -> Result<()> {
+        self.apply_overrides(std::env::vars())
+    }
+
+    /// Applies `DSN_*` overrides from the given variables; list values are comma separated.
+    pub fn apply_overrides(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+        for (key, value) in vars {
+            match key.as_str() {
+                "DSN_LISTEN" => self.network.listen_addrs = parse_list(&key, &value)?,
+                "DSN_DATA_DIR" => self.storage.data_dir = Some(PathBuf::from(value)),
+                "DSN_BOOTSTRAP" => self.bootstrap.peers = parse_list(&key, &value)?,
+                "DSN_EXTERNAL_ADDRS" => self.network.external_addrs = parse_list(&key, &value)?,
+                "DSN_RELAYS" => self.relay.relays = parse_list(&key, &value)?,
+                "DSN_RELAY_SERVER" => self.relay.server.enabled = value.parse().with_context(|| format!("{key} must be `true` or `false`"))?,
+                "DSN_RECORD_STORE" => self.kademlia.record_store.backend = match value.as_str() {
+                    "memory" => RecordStoreBackend::Memory,
+                    "disk" => RecordStoreBackend::Disk,
+                    other => bail!("{key} must be `memory` or `disk`, got `{other}`"),
+                },
+                "DSN_CONTROL_SOCKET" => self.control.socket_path = Some(PathBuf::from(value)),
+                "DSN_MDNS" => self.mdns.enabled = value.parse().with_context(|| format!("{key} must be `true` or `false`"))?,
+                "DSN_DHT_MODE" => self.kademlia.mode = match value.as_str() {
+                    "auto" => DhtMode::Auto,
+                    "client" => DhtMode::Client,
+                    "server" => DhtMode::Server,
+                    other => bail!("{key} must be `auto`, `client` or `server`, got `{other}`"),
+                },
+                "DSN_TOFU" => self.security.tofu = match value.as_str() {
+                    "warn" => TofuMode::Warn,
+                    "block" => TofuMode::Block,
+                    other => bail!("{key} must be `warn` or `block`, got `{other}`"),
+                },
+                "DSN_MAX_CONNECTIONS" => self.limits.max_established_total = Some(value.parse().with_context(|| format!("{key} must be a number"))?),
+                "DSN_YAMUX_MAX_STREAMS" => self.yamux.max_num_streams = value.parse().with_context(|| format!("{key} must be a number"))?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_list<T: std::str::FromStr>(key: &str, value: &str) -> Result<Vec<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| item.parse().with_context(|| format!("Invalid value `{item}` in {key}")))
+        .collect()
+}
+
+/// (De)serializes a `Duration` as whole seconds so config files stay readable.
+pub mod duration_secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_partial_file_keeps_defaults() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("config.json");
+        fs::write(&path, r#"{"kademlia": {"replication_factor": 5, "record_store": {"backend": "memory"}}, "mdns": {"enabled": false}}"#).unwrap();
+
+        let config = Config::load_from_file(&path).unwrap();
+        assert_eq!(config.kademlia.replication_factor.get(), 5);
+        assert_eq!(config.kademlia.query_timeout, Duration::from_secs(20));
+        assert_eq!(config.kademlia.record_store.backend, RecordStoreBackend::Memory);
+        assert!(!config.mdns.enabled);
+        assert_eq!(config.yamux.max_num_streams, 256);
+        assert_eq!(config.network.listen_addrs, NetworkOptions::default().listen_addrs);
+        assert_eq!(config.network.listen_addrs.len(), 2, "Both TCP and QUIC listen by default");
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("config.json");
+        fs::write(&path, r#"{"kademlia": {"replication": 5}}"#).unwrap();
+        assert!(Config::load_from_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_config_roundtrip() {
+        let config = Config::default();
+        let json = serde_json::to_string(&config).unwrap();
+        let decoded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.identify.interval, config.identify.interval);
+        assert_eq!(decoded.bootstrap.interval, config.bootstrap.interval);
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let mut config = Config::ephemeral();
+        config.apply_overrides([
+            ("DSN_LISTEN".to_string(), "/ip4/127.0.0.1/tcp/4001, /ip4/127.0.0.1/tcp/4002".to_string()),
+            ("DSN_RECORD_STORE".to_string(), "disk".to_string()),
+            ("DSN_MDNS".to_string(), "false".to_string()),
+            ("DSN_TOFU".to_string(), "block".to_string()),
+            ("DSN_MAX_CONNECTIONS".to_string(), "64".to_string()),
+            ("DSN_RELAYS".to_string(), "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".to_string()),
+            ("DSN_RELAY_SERVER".to_string(), "true".to_string()),
+            ("DSN_DHT_MODE".to_string(), "server".to_string()),
+            ("HOME".to_string(), "/ignored".to_string()),
+        ]).unwrap();
+        assert_eq!(config.network.listen_addrs.len(), 2);
+        assert_eq!(config.kademlia.record_store.backend, RecordStoreBackend::Disk);
+        assert!(!config.mdns.enabled);
+        assert_eq!(config.security.tofu, TofuMode::Block);
+        assert_eq!(config.limits.max_established_total, Some(64));
+        assert_eq!(config.relay.relays.len(), 1);
+        assert!(config.relay.server.enabled);
+        assert_eq!(config.kademlia.mode, DhtMode::Server);
+
+        config.apply_overrides([("DSN_DATA_DIR".to_string(), "/tmp/dsn-node-b".to_string())]).unwrap();
+        assert_eq!(config.data_dir().unwrap(), PathBuf::from("/tmp/dsn-node-b"));
+        config.kademlia.record_store.backend = RecordStoreBackend::Disk;
+        assert_eq!(config.record_store().unwrap().path, Some(PathBuf::from("/tmp/dsn-node-b/kad-records.json")));
+
+        assert!(config.apply_overrides([("DSN_RECORD_STORE".to_string(), "tape".to_string())]).is_err());
+        assert!(config.apply_overrides([("DSN_LISTEN".to_string(), "not-an-addr".to_string())]).is_err());
+    }
+}
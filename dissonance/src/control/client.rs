@@ -0,0 +1,74 @@
+use std::{collections::VecDeque, path::Path};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{unix::{OwnedReadHalf, OwnedWriteHalf}, UnixStream},
+};
+
+use super::protocol::{ControlEvent, RpcNotification, RpcResponse, JSONRPC_VERSION};
+
+/// Minimal client for the control socket, used by tools and tests.
+pub struct ControlClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+    // Events that arrived while waiting for a response.
+    events: VecDeque<ControlEvent>,
+}
+
+impl ControlClient {
+    pub async fn connect(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path).await.with_context(|| format!("Failed to connect to {}", path.display()))?;
+        let (reader, writer) = stream.into_split();
+        Ok(ControlClient { lines: BufReader::new(reader).lines(), writer, next_id: 0, events: VecDeque::new() })
+    }
+
+    /// Calls `method` and waits for its result. Errors returned by the node are `RpcError`s.
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let mut line = serde_json::to_vec(&json!({"jsonrpc": JSONRPC_VERSION, "id": id, "method": method, "params": params}))?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await.context("Failed to write to control socket")?;
+
+        loop {
+            let message = self.read_message().await?;
+            if message.get("method").is_some() {
+                let notification: RpcNotification = serde_json::from_value(message)?;
+                self.events.push_back(notification.params);
+                continue;
+            }
+            let response: RpcResponse = serde_json::from_value(message)?;
+            if response.id != json!(id) {
+                continue;
+            }
+            return match (response.result, response.error) {
+                (_, Some(error)) => Err(error.into()),
+                (result, None) => Ok(result.unwrap_or(Value::Null)),
+            };
+        }
+    }
+
+    /// Waits for the next event; call `subscribe` first.
+    pub async fn next_event(&mut self) -> Result<ControlEvent> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            let message = self.read_message().await?;
+            if message.get("method").is_some() {
+                let notification: RpcNotification = serde_json::from_value(message)?;
+                return Ok(notification.params);
+            }
+        }
+    }
+
+    async fn read_message(&mut self) -> Result<Value> {
+        match self.lines.next_line().await.context("Failed to read from control socket")? {
+            Some(line) => serde_json::from_str(&line).context("Node sent invalid JSON"),
+            None => bail!("Control socket closed"),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod protocol;
+pub mod server;
+pub mod client;
+
+pub use client::ControlClient;
+pub use protocol::{ControlEvent, ControlRequest, RpcError};
+pub use server::ControlServer;
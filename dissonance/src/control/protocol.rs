@@ -0,0 +1,210 @@
+use std::fmt;
+
+use libp2p::{Multiaddr, PeerId};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+pub use crate::node::NodeEvent as ControlEvent;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The command was understood but the node could not carry it out.
+pub const COMMAND_FAILED: i64 = -32000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+
+    pub fn failed(message: impl Into<String>) -> Self {
+        Self::new(COMMAND_FAILED, message)
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// A JSON-RPC 2.0 request as read off the socket, one per line. Requests without an `id` are
+/// notifications and get no response.
+#[derive(Debug, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        RpcResponse { jsonrpc: JSONRPC_VERSION.to_string(), id, result, error }
+    }
+}
+
+/// Server-initiated message carrying an event to subscribed clients.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: ControlEvent,
+}
+
+impl RpcNotification {
+    pub fn event(event: ControlEvent) -> Self {
+        RpcNotification { jsonrpc: JSONRPC_VERSION.to_string(), method: "event".to_string(), params: event }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DialParams {
+    pub address: Multiaddr,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PeerAddressParams {
+    pub peer: PeerId,
+    pub address: Multiaddr,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SendParams {
+    pub peer: PeerId,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PeerParams {
+    pub peer: PeerId,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddressParams {
+    pub address: Multiaddr,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PruneParams {
+    /// Seconds since a peer was last seen after which it is forgotten.
+    pub max_age: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoomParams {
+    pub room: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PublishParams {
+    pub room: String,
+    pub text: String,
+}
+
+/// Commands carried out on the node on behalf of a control client.
+#[derive(Debug, Clone)]
+pub enum ControlRequest {
+    Identity,
+    Peers,
+    AddPeerAddress(PeerAddressParams),
+    Dial(DialParams),
+    Send(SendParams),
+    JoinRoom(RoomParams),
+    LeaveRoom(RoomParams),
+    Publish(PublishParams),
+    Rooms,
+    SafetyNumber(PeerParams),
+    Verify(PeerParams),
+    Unverify(PeerParams),
+    Unpin(AddressParams),
+    Prune(PruneParams),
+}
+
+impl ControlRequest {
+    pub fn parse(method: &str, params: Value) -> Result<Self, RpcError> {
+        Ok(match method {
+            "identity" => ControlRequest::Identity,
+            "peers" => ControlRequest::Peers,
+            "add_peer_address" => ControlRequest::AddPeerAddress(parse_params(params)?),
+            "dial" => ControlRequest::Dial(parse_params(params)?),
+            "send" => ControlRequest::Send(parse_params(params)?),
+            "join_room" => ControlRequest::JoinRoom(parse_params(params)?),
+            "leave_room" => ControlRequest::LeaveRoom(parse_params(params)?),
+            "publish" => ControlRequest::Publish(parse_params(params)?),
+            "rooms" => ControlRequest::Rooms,
+            "safety_number" => ControlRequest::SafetyNumber(parse_params(params)?),
+            "verify" => ControlRequest::Verify(parse_params(params)?),
+            "unverify" => ControlRequest::Unverify(parse_params(params)?),
+            "unpin" => ControlRequest::Unpin(parse_params(params)?),
+            "prune" => ControlRequest::Prune(parse_params(params)?),
+            other => return Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method `{other}`"))),
+        })
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_requests() {
+        let peer = PeerId::random();
+        let request = ControlRequest::parse("send", json!({"peer": peer.to_string(), "text": "hi"})).unwrap();
+        assert!(matches!(request, ControlRequest::Send(SendParams { peer: parsed, .. }) if parsed == peer));
+        assert!(matches!(ControlRequest::parse("identity", Value::Null), Ok(ControlRequest::Identity)));
+        assert!(matches!(ControlRequest::parse("prune", json!({"max_age": 60})), Ok(ControlRequest::Prune(PruneParams { max_age: 60 }))));
+
+        assert_eq!(ControlRequest::parse("shutdown", Value::Null).unwrap_err().code, METHOD_NOT_FOUND);
+        assert_eq!(ControlRequest::parse("dial", json!({"address": "nope"})).unwrap_err().code, INVALID_PARAMS);
+        assert_eq!(ControlRequest::parse("join_room", Value::Null).unwrap_err().code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_response_has_result_or_error() {
+        let ok = serde_json::to_value(RpcResponse::new(json!(1), Ok(json!(true)))).unwrap();
+        assert_eq!(ok, json!({"jsonrpc": "2.0", "id": 1, "result": true}));
+
+        let err = serde_json::to_value(RpcResponse::new(json!("a"), Err(RpcError::failed("boom")))).unwrap();
+        assert_eq!(err, json!({"jsonrpc": "2.0", "id": "a", "error": {"code": COMMAND_FAILED, "message": "boom"}}));
+    }
+
+    #[test]
+    fn test_event_notification_shape() {
+        let peer = PeerId::random();
+        let notification = serde_json::to_value(RpcNotification::event(ControlEvent::PeerDisconnected { peer })).unwrap();
+        assert_eq!(notification, json!({"jsonrpc": "2.0", "method": "event", "params": {"type": "peer_disconnected", "peer": peer.to_string()}}));
+    }
+}
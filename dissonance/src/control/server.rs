@@ -0,0 +1,248 @@
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{unix::OwnedWriteHalf, UnixListener, UnixStream},
+    sync::broadcast,
+    task::JoinHandle,
+};
+
+use crate::node::NodeHandle;
+
+use super::protocol::{
+    ControlEvent, ControlRequest, RpcError, RpcNotification, RpcRequest, RpcResponse, INVALID_REQUEST,
+    JSONRPC_VERSION, PARSE_ERROR,
+};
+
+/// Local control plane: JSON-RPC 2.0 over a Unix domain socket, one message per line.
+/// Requests are carried out through a `NodeHandle`; clients that call `subscribe` receive the
+/// node's events as notifications.
+pub struct ControlServer {
+    path: PathBuf,
+    accept_task: JoinHandle<()>,
+}
+
+impl ControlServer {
+    /// Binds the socket at `path`, replacing a stale one left by a previous run.
+    /// Must be called from within a tokio runtime.
+    pub fn bind(path: &Path, node: NodeHandle) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create control socket directory")?;
+        }
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                anyhow::bail!("Another node is already listening on {}", path.display());
+            }
+            fs::remove_file(path).context("Failed to remove stale control socket")?;
+        }
+        let listener = UnixListener::bind(path).context("Failed to bind control socket")?;
+        // Anyone who can connect can send messages as this node.
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).context("Failed to restrict control socket permissions")?;
+
+        let accept_task = tokio::spawn(accept_loop(listener, node));
+        Ok(ControlServer { path: path.to_path_buf(), accept_task })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+async fn accept_loop(listener: UnixListener, node: NodeHandle) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_connection(stream, node.clone()));
+            }
+            Err(e) => {
+                tracing::warn!("Control socket accept failed: {e}");
+                return;
+            }
+        }
+    }
+}
+
+async fn serve_connection(stream: UnixStream, node: NodeHandle) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut subscription: Option<broadcast::Receiver<ControlEvent>> = None;
+
+    loop {
+        let result = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => match handle_line(&line, &node, &mut subscription).await {
+                    Some(response) => write_message(&mut writer, &response).await,
+                    None => Ok(()),
+                },
+                _ => return,
+            },
+            event = next_event(&mut subscription) => match event {
+                Ok(event) => write_message(&mut writer, &RpcNotification::event(event)).await,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    write_message(&mut writer, &RpcNotification::event(ControlEvent::Lagged { missed })).await
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+        };
+        if result.is_err() {
+            return;
+        }
+    }
+}
+
+async fn next_event(subscription: &mut Option<broadcast::Receiver<ControlEvent>>) -> Result<ControlEvent, broadcast::error::RecvError> {
+    match subscription {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn handle_line(
+    line: &str,
+    node: &NodeHandle,
+    subscription: &mut Option<broadcast::Receiver<ControlEvent>>,
+) -> Option<RpcResponse> {
+    let request: RpcRequest = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return Some(RpcResponse::new(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())))),
+    };
+    let id = request.id.clone();
+    let result = if request.jsonrpc != JSONRPC_VERSION {
+        Err(RpcError::new(INVALID_REQUEST, "Only JSON-RPC 2.0 is supported"))
+    } else {
+        match request.method.as_str() {
+            // Subscriptions belong to the connection, so they are handled here rather than by the node.
+            "subscribe" => {
+                *subscription = Some(node.subscribe());
+                Ok(Value::Bool(true))
+            }
+            "unsubscribe" => Ok(Value::Bool(subscription.take().is_some())),
+            method => match ControlRequest::parse(method, request.params) {
+                Ok(request) => dispatch(node, request).await.map_err(|e| RpcError::failed(format!("{e:#}"))),
+                Err(e) => Err(e),
+            },
+        }
+    };
+    id.map(|id| RpcResponse::new(id, result))
+}
+
+async fn dispatch(node: &NodeHandle, request: ControlRequest) -> Result<Value> {
+    Ok(match request {
+        ControlRequest::Identity => serde_json::to_value(node.info().await?)?,
+        ControlRequest::Peers => serde_json::to_value(node.peers().await?)?,
+        ControlRequest::AddPeerAddress(params) => {
+            node.add_peer_address(params.peer, params.address).await?;
+            Value::Bool(true)
+        },
+        ControlRequest::Dial(params) => json!({ "peer_id": node.dial(params.address).await? }),
+        ControlRequest::Send(params) => {
+            let ack = node.send(params.peer, params.text).await?;
+            json!({ "message_id": ack.id, "status": ack.status })
+        },
+        ControlRequest::JoinRoom(params) => Value::Bool(node.join_room(params.room).await?),
+        ControlRequest::LeaveRoom(params) => Value::Bool(node.leave_room(params.room).await?),
+        ControlRequest::Publish(params) => json!({ "message_id": node.publish(params.room, params.text).await? }),
+        ControlRequest::Rooms => serde_json::to_value(node.rooms().await?)?,
+        ControlRequest::SafetyNumber(params) => json!({ "safety_number": node.safety_number(params.peer).await? }),
+        ControlRequest::Verify(params) => {
+            node.set_verified(params.peer, true).await?;
+            Value::Bool(true)
+        },
+        ControlRequest::Unverify(params) => {
+            node.set_verified(params.peer, false).await?;
+            Value::Bool(true)
+        },
+        ControlRequest::Unpin(params) => json!({ "peer": node.unpin_address(params.address).await? }),
+        ControlRequest::Prune(params) => json!({ "removed": node.prune_peers(Duration::from_secs(params.max_age)).await? }),
+    })
+}
+
+async fn write_message(writer: &mut OwnedWriteHalf, message: &impl Serialize) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(message).expect("control messages always serialize");
+    line.push(b'\n');
+    writer.write_all(&line).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::control::ControlClient;
+    use crate::control::protocol::METHOD_NOT_FOUND;
+    use crate::node::Node;
+    use crate::NodeIdentity;
+    use tempfile::tempdir;
+
+    fn spawn_node() -> NodeHandle {
+        let mut config = Config::ephemeral();
+        config.network.listen_addrs = vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()];
+        config.mdns.enabled = false;
+        Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_requests_reach_node_and_events_reach_subscribers() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("control.sock");
+        let node = spawn_node();
+        let server = ControlServer::bind(&path, node.clone()).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let mut client = ControlClient::connect(&path).await.unwrap();
+        let identity = client.call("identity", Value::Null).await.unwrap();
+        assert_eq!(identity["peer_id"], json!(node.peer_id().to_string()));
+
+        let error = client.call("shutdown", Value::Null).await.unwrap_err();
+        assert_eq!(error.downcast::<RpcError>().unwrap().code, METHOD_NOT_FOUND);
+
+        client.call("subscribe", Value::Null).await.unwrap();
+        assert_eq!(client.call("join_room", json!({"room": "lobby"})).await.unwrap(), json!(true));
+        let rooms = client.call("rooms", Value::Null).await.unwrap();
+        assert_eq!(rooms[0]["room"], json!("lobby"));
+        assert_eq!(client.call("unpin", json!({"address": "/ip4/127.0.0.1/tcp/1"})).await.unwrap(), json!({"peer": null}));
+        assert_eq!(client.call("prune", json!({"max_age": 60})).await.unwrap(), json!({"removed": 0}));
+
+        // A second node connecting shows up on the event stream.
+        let other = spawn_node();
+        let address = loop {
+            if let Some(address) = node.info().await.unwrap().listen_addrs.pop() {
+                break address;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        other.dial(address).await.unwrap();
+        loop {
+            if let ControlEvent::PeerConnected { peer, .. } = client.next_event().await.unwrap() {
+                assert_eq!(peer, other.peer_id());
+                break;
+            }
+        }
+
+        drop(server);
+        assert!(!path.exists(), "Socket should be removed on shutdown");
+    }
+
+    #[tokio::test]
+    async fn test_refuses_to_replace_live_socket() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("control.sock");
+        let node = spawn_node();
+        let _server = ControlServer::bind(&path, node.clone()).unwrap();
+        assert!(ControlServer::bind(&path, node).is_err());
+    }
+}
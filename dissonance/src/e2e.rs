@@ -0,0 +1,308 @@
+//! Application-layer end-to-end encryption for chat payloads.
+//!
+//! Noise only protects a single hop, so anything relayed or stored on the DHT is sealed here
+//! first. Each node's X25519 key is derived from its ed25519 `NodeIdentity`, which means the
+//! recipient key can be recovered from a `PeerId` alone. Every envelope is sealed with a fresh
+//! ephemeral key mixed with the static-static secret, so each message gets its own key and only
+//! the real sender could have produced it.
+
+use std::fmt;
+
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::{VerifyingKey, PUBLIC_KEY_LENGTH};
+use hkdf::Hkdf;
+use libp2p::{identity, PeerId};
+use rand::TryRngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+
+use crate::NodeIdentity;
+
+const ENVELOPE_VERSION: u8 = 1;
+const KEY_INFO: &[u8] = b"dissonance/e2e/v1";
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum E2eError {
+    UnsupportedVersion(u8),
+    UnsupportedPeerKey(PeerId),
+    NotARecipient,
+    Decryption,
+    Randomness(String),
+}
+
+impl fmt::Display for E2eError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            E2eError::UnsupportedVersion(version) => write!(f, "unsupported envelope version {version}"),
+            E2eError::UnsupportedPeerKey(peer) => write!(f, "peer {peer} does not embed an ed25519 public key"),
+            E2eError::NotARecipient => write!(f, "envelope is not addressed to this node"),
+            E2eError::Decryption => write!(f, "envelope failed authentication"),
+            E2eError::Randomness(e) => write!(f, "failed to gather randomness: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for E2eError {}
+
+/// A payload sealed for a single recipient.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u8,
+    pub sender: PeerId,
+    pub recipient: PeerId,
+    ephemeral: [u8; 32],
+    nonce: [u8; NONCE_LENGTH],
+    ciphertext: Vec<u8>,
+}
+
+/// A content key wrapped for one member of a group envelope.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WrappedKey {
+    pub recipient: PeerId,
+    wrapped: Vec<u8>,
+}
+
+/// A payload sealed once under a random content key, with that key wrapped for every recipient.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupEnvelope {
+    pub version: u8,
+    pub sender: PeerId,
+    ephemeral: [u8; 32],
+    nonce: [u8; NONCE_LENGTH],
+    ciphertext: Vec<u8>,
+    pub recipients: Vec<WrappedKey>,
+}
+
+impl GroupEnvelope {
+    pub fn ciphertext_len(&self) -> usize {
+        self.ciphertext.len()
+    }
+}
+
+#[derive(Clone)]
+pub struct E2eKeys {
+    peer_id: PeerId,
+    secret: StaticSecret,
+}
+
+impl E2eKeys {
+    pub fn from_identity(identity: &NodeIdentity) -> Self {
+        E2eKeys { peer_id: identity.peer_id(), secret: StaticSecret::from(identity.signing_key.to_scalar_bytes()) }
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    pub fn public_key(&self) -> X25519PublicKey {
+        X25519PublicKey::from(&self.secret)
+    }
+
+    pub fn seal(&self, recipient: &PeerId, plaintext: &[u8]) -> Result<Envelope, E2eError> {
+        let recipient_key = peer_public_key(recipient)?;
+        let ephemeral = StaticSecret::from(random_bytes::<32>()?);
+        let ephemeral_public = X25519PublicKey::from(&ephemeral).to_bytes();
+
+        let key = derive_key(
+            ephemeral.diffie_hellman(&recipient_key).as_bytes(),
+            self.secret.diffie_hellman(&recipient_key).as_bytes(),
+            &ephemeral_public,
+            &self.peer_id,
+            recipient,
+        );
+        let nonce = random_bytes::<NONCE_LENGTH>()?;
+        let aad = envelope_aad(&self.peer_id, Some(recipient), &ephemeral_public);
+        let ciphertext = encrypt(&key, &nonce, plaintext, &aad)?;
+
+        Ok(Envelope { version: ENVELOPE_VERSION, sender: self.peer_id, recipient: *recipient, ephemeral: ephemeral_public, nonce, ciphertext })
+    }
+
+    pub fn open(&self, envelope: &Envelope) -> Result<Vec<u8>, E2eError> {
+        if envelope.version != ENVELOPE_VERSION {
+            return Err(E2eError::UnsupportedVersion(envelope.version));
+        }
+        if envelope.recipient != self.peer_id {
+            return Err(E2eError::NotARecipient);
+        }
+        let key = self.recipient_key(&envelope.sender, &envelope.ephemeral)?;
+        let aad = envelope_aad(&envelope.sender, Some(&self.peer_id), &envelope.ephemeral);
+        decrypt(&key, &envelope.nonce, &envelope.ciphertext, &aad)
+    }
+
+    pub fn seal_group(&self, recipients: &[PeerId], plaintext: &[u8]) -> Result<GroupEnvelope, E2eError> {
+        let content_key = random_bytes::<32>()?;
+        let ephemeral = StaticSecret::from(random_bytes::<32>()?);
+        let ephemeral_public = X25519PublicKey::from(&ephemeral).to_bytes();
+
+        let mut wrapped_keys = Vec::with_capacity(recipients.len());
+        for recipient in recipients {
+            let recipient_key = peer_public_key(recipient)?;
+            let wrapping_key = derive_key(
+                ephemeral.diffie_hellman(&recipient_key).as_bytes(),
+                self.secret.diffie_hellman(&recipient_key).as_bytes(),
+                &ephemeral_public,
+                &self.peer_id,
+                recipient,
+            );
+            // Each wrapping key is bound to this ephemeral key and recipient, so it is only ever used once.
+            let wrapped = encrypt(&wrapping_key, &[0u8; NONCE_LENGTH], &content_key, &envelope_aad(&self.peer_id, Some(recipient), &ephemeral_public))?;
+            wrapped_keys.push(WrappedKey { recipient: *recipient, wrapped });
+        }
+
+        let nonce = random_bytes::<NONCE_LENGTH>()?;
+        let ciphertext = encrypt(&content_key, &nonce, plaintext, &envelope_aad(&self.peer_id, None, &ephemeral_public))?;
+
+        Ok(GroupEnvelope { version: ENVELOPE_VERSION, sender: self.peer_id, ephemeral: ephemeral_public, nonce, ciphertext, recipients: wrapped_keys })
+    }
+
+    pub fn open_group(&self, envelope: &GroupEnvelope) -> Result<Vec<u8>, E2eError> {
+        if envelope.version != ENVELOPE_VERSION {
+            return Err(E2eError::UnsupportedVersion(envelope.version));
+        }
+        let wrapped = envelope.recipients.iter()
+            .find(|wrapped| wrapped.recipient == self.peer_id)
+            .ok_or(E2eError::NotARecipient)?;
+
+        let wrapping_key = self.recipient_key(&envelope.sender, &envelope.ephemeral)?;
+        let content_key = decrypt(&wrapping_key, &[0u8; NONCE_LENGTH], &wrapped.wrapped, &envelope_aad(&envelope.sender, Some(&self.peer_id), &envelope.ephemeral))?;
+        let content_key: [u8; 32] = content_key.try_into().map_err(|_| E2eError::Decryption)?;
+
+        decrypt(&content_key, &envelope.nonce, &envelope.ciphertext, &envelope_aad(&envelope.sender, None, &envelope.ephemeral))
+    }
+
+    fn recipient_key(&self, sender: &PeerId, ephemeral: &[u8; 32]) -> Result<[u8; 32], E2eError> {
+        let sender_key = peer_public_key(sender)?;
+        let ephemeral_key = X25519PublicKey::from(*ephemeral);
+        Ok(derive_key(
+            self.secret.diffie_hellman(&ephemeral_key).as_bytes(),
+            self.secret.diffie_hellman(&sender_key).as_bytes(),
+            ephemeral,
+            sender,
+            &self.peer_id,
+        ))
+    }
+}
+
+/// The ed25519 public key inlined in a `PeerId`.
+pub(crate) fn peer_ed25519_key(peer: &PeerId) -> Result<[u8; PUBLIC_KEY_LENGTH], E2eError> {
+    let multihash = peer.as_ref();
+    // Code 0 is the identity multihash, which is how libp2p inlines small public keys.
+    if multihash.code() != 0 {
+        return Err(E2eError::UnsupportedPeerKey(*peer));
+    }
+    identity::PublicKey::try_decode_protobuf(multihash.digest())
+        .ok()
+        .and_then(|key| key.try_into_ed25519().ok())
+        .map(|key| key.to_bytes())
+        .ok_or(E2eError::UnsupportedPeerKey(*peer))
+}
+
+/// Recovers a peer's X25519 key from the ed25519 public key inlined in its `PeerId`.
+pub(crate) fn peer_public_key(peer: &PeerId) -> Result<X25519PublicKey, E2eError> {
+    let verifying_key = VerifyingKey::from_bytes(&peer_ed25519_key(peer)?).map_err(|_| E2eError::UnsupportedPeerKey(*peer))?;
+    Ok(X25519PublicKey::from(verifying_key.to_montgomery().to_bytes()))
+}
+
+fn derive_key(ephemeral_shared: &[u8; 32], static_shared: &[u8; 32], ephemeral_public: &[u8; 32], sender: &PeerId, recipient: &PeerId) -> [u8; 32] {
+    let mut ikm = [0u8; 64];
+    ikm[..32].copy_from_slice(ephemeral_shared);
+    ikm[32..].copy_from_slice(static_shared);
+
+    let mut info = KEY_INFO.to_vec();
+    info.extend_from_slice(&sender.to_bytes());
+    info.extend_from_slice(&recipient.to_bytes());
+
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(ephemeral_public), &ikm)
+        .expand(&info, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+fn envelope_aad(sender: &PeerId, recipient: Option<&PeerId>, ephemeral_public: &[u8; 32]) -> Vec<u8> {
+    let mut aad = vec![ENVELOPE_VERSION];
+    aad.extend_from_slice(&sender.to_bytes());
+    if let Some(recipient) = recipient {
+        aad.extend_from_slice(&recipient.to_bytes());
+    }
+    aad.extend_from_slice(ephemeral_public);
+    aad
+}
+
+fn encrypt(key: &[u8; 32], nonce: &[u8; NONCE_LENGTH], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, E2eError> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(Nonce::from_slice(nonce), Payload { msg: plaintext, aad })
+        .map_err(|_| E2eError::Decryption)
+}
+
+fn decrypt(key: &[u8; 32], nonce: &[u8; NONCE_LENGTH], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, E2eError> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| E2eError::Decryption)
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], E2eError> {
+    let mut bytes = [0u8; N];
+    rand::rngs::OsRng.try_fill_bytes(&mut bytes).map_err(|e| E2eError::Randomness(e.to_string()))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> E2eKeys {
+        E2eKeys::from_identity(&NodeIdentity::generate_ephemeral().unwrap())
+    }
+
+    #[test]
+    fn test_peer_public_key_matches_derived_key() {
+        let alice = keys();
+        assert_eq!(peer_public_key(&alice.peer_id()).unwrap(), alice.public_key());
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let (alice, bob) = (keys(), keys());
+        let envelope = alice.seal(&bob.peer_id(), b"hello bob").unwrap();
+        assert_eq!(bob.open(&envelope).unwrap(), b"hello bob");
+
+        let second = alice.seal(&bob.peer_id(), b"hello bob").unwrap();
+        assert_ne!(envelope.ephemeral, second.ephemeral, "every envelope must use a fresh key");
+        assert_ne!(envelope.ciphertext, second.ciphertext);
+    }
+
+    #[test]
+    fn test_open_rejects_wrong_recipient_and_tampering() {
+        let (alice, bob, eve) = (keys(), keys(), keys());
+        let envelope = alice.seal(&bob.peer_id(), b"secret").unwrap();
+        assert_eq!(eve.open(&envelope).unwrap_err(), E2eError::NotARecipient);
+
+        let mut redirected = envelope.clone();
+        redirected.recipient = eve.peer_id();
+        assert_eq!(eve.open(&redirected).unwrap_err(), E2eError::Decryption);
+
+        let mut spoofed = envelope.clone();
+        spoofed.sender = eve.peer_id();
+        assert_eq!(bob.open(&spoofed).unwrap_err(), E2eError::Decryption);
+
+        let mut tampered = envelope;
+        tampered.ciphertext[0] ^= 1;
+        assert_eq!(bob.open(&tampered).unwrap_err(), E2eError::Decryption);
+    }
+
+    #[test]
+    fn test_group_envelope() {
+        let (alice, bob, carol, eve) = (keys(), keys(), keys(), keys());
+        let envelope = alice.seal_group(&[bob.peer_id(), carol.peer_id()], b"hello room").unwrap();
+        assert_eq!(bob.open_group(&envelope).unwrap(), b"hello room");
+        assert_eq!(carol.open_group(&envelope).unwrap(), b"hello room");
+        assert_eq!(eve.open_group(&envelope).unwrap_err(), E2eError::NotARecipient);
+
+        let mut stolen = envelope.clone();
+        stolen.recipients[0].recipient = eve.peer_id();
+        assert_eq!(eve.open_group(&stolen).unwrap_err(), E2eError::Decryption);
+    }
+}
@@ -1,4 +1,11 @@
+pub mod config;
+pub mod control;
 pub mod network;
+pub mod node;
+pub mod profile;
+pub mod reputation;
 pub mod store;
+pub mod verification;
+pub mod e2e;
 
 pub use network::identity::NodeIdentity;
@@ -1,183 +1,514 @@
-use std::{error::Error, time::SystemTime};
-use futures::stream::StreamExt;
-use libp2p::identify::Info;
-use libp2p::{
-    swarm::SwarmEvent,
-};
+mod cli;
+
+use std::{error::Error, fs, path::Path, time::{Duration, SystemTime}};
+use anyhow::{bail, Context};
+use clap::Parser;
+use libp2p::{Multiaddr, PeerId};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tracing_subscriber::EnvFilter;
 
-use dissonance::network::behaviour::{DissonanceEvent};
-use dissonance::network::builder::{build_swarm};
+use cli::{Cli, Command, GateCommand, IdentityCommand, LogFormat, PeersCommand, ProfileCommand, RunArgs};
+
+use dissonance::config::Config;
+use dissonance::control::{ControlClient, ControlServer};
+use dissonance::network::behaviours::chat::{DeliveryStatus, MessageId};
+use dissonance::node::{Node, NodeHandle};
+use dissonance::profile::Profile;
+use dissonance::network::identity::PASSPHRASE_ENV;
 use dissonance::NodeIdentity;
-use dissonance::store::{PeerStore, PeerInfo};
+use dissonance::store::PeerStore;
+use dissonance::verification::safety_number_for;
 
-use libp2p::kad::Event as KademliaEvent;
+/// How long `dial` and `send` wait before giving up.
+const ONE_SHOT_TIMEOUT: Duration = Duration::from_secs(30);
 
+const INPUT_USAGE: &str = "Commands: `<peer-id> <message>`, `/join <room>`, `/leave <room>`, `/room <room> <message>`, `/rooms`, `/safety <peer-id>`, `/verify <peer-id>`";
 
+async fn handle_input(node: NodeHandle, line: String) {
+    let (command, rest) = line.split_once(' ').unwrap_or((&line, ""));
+    match command {
+        "" => {},
+        "/join" if !rest.is_empty() => match node.join_room(rest).await {
+            Ok(true) => {},
+            Ok(false) => println!("[ROOM] Already a member of {rest}"),
+            Err(e) => println!("[ROOM] {e:#}"),
+        },
+        "/leave" if !rest.is_empty() => {
+            if let Ok(false) = node.leave_room(rest).await {
+                println!("[ROOM] Not a member of {rest}");
+            }
+        },
+        "/room" => {
+            let Some((room, body)) = rest.split_once(' ') else {
+                println!("Usage: /room <room> <message>");
+                return;
+            };
+            if let Err(e) = node.publish(room, body).await {
+                println!("[ROOM] {e:#}");
+            }
+        },
+        "/rooms" => {
+            for room in node.rooms().await.unwrap_or_default() {
+                println!("[ROOM] {} ({} members)", room.room, room.members.len());
+            }
+        },
+        "/safety" | "/verify" if !rest.is_empty() => {
+            let peer: PeerId = match rest.parse() {
+                Ok(peer) => peer,
+                Err(e) => {
+                    println!("Invalid peer id {rest}: {e}");
+                    return;
+                }
+            };
+            let result = if command == "/safety" {
+                node.safety_number(peer).await.map(|number| println!("[VERIFY] Safety number with {peer}: {number}"))
+            } else {
+                node.set_verified(peer, true).await.map(|()| println!("[VERIFY] Marked {peer} as verified"))
+            };
+            if let Err(e) = result {
+                println!("[VERIFY] {e:#}");
+            }
+        },
+        _ if command.starts_with('/') => println!("{INPUT_USAGE}"),
+        peer => {
+            let peer: PeerId = match peer.parse() {
+                Ok(peer) => peer,
+                Err(e) => {
+                    println!("Invalid peer id {peer}: {e}");
+                    return;
+                }
+            };
+            // Delivery and acknowledgements are reported by the node itself.
+            if let Err(e) = node.send(peer, rest).await {
+                println!("[CHAT] {e:#}");
+            }
+        }
+    }
+}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .try_init();
-    let args: Vec<String> = std::env::args().collect();
+fn init_tracing(format: LogFormat) {
+    // Logs go to stderr so command output on stdout stays scriptable.
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("dissonance=info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+    let _ = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
+}
 
-    println!("Initialising node identity");
-    let node_identity = if args.contains(&"--ephemeral".to_string()) {
-        NodeIdentity::generate_ephemeral()?
+fn load_identity(config: &Config) -> anyhow::Result<NodeIdentity> {
+    let path = NodeIdentity::identity_path(&config.data_dir()?);
+    NodeIdentity::load_or_create(&path, &identity_passphrase(&path)?)
+}
+
+/// Passphrase for the identity at `path`. Missing and plaintext identities are about to be
+/// encrypted, so they get a new, confirmed one.
+fn identity_passphrase(path: &Path) -> anyhow::Result<String> {
+    if path.exists() && NodeIdentity::is_encrypted(path)? {
+        existing_passphrase(path)
     } else {
-        NodeIdentity::get_identity()?
-    };
+        new_passphrase()
+    }
+}
 
-    let mut peer_store = PeerStore::new();
-    let mut swarm = build_swarm(&node_identity)?;
-    println!("Local peer ID: {}", swarm.local_peer_id());
+fn existing_passphrase(path: &Path) -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    prompt_passphrase(&format!("Passphrase for {}: ", path.display()))
+}
 
-    assert_eq!(swarm.local_peer_id(), &node_identity.peer_id());
+fn new_passphrase() -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = prompt_passphrase("New identity passphrase: ")?;
+    if prompt_passphrase("Repeat passphrase: ")? != passphrase {
+        bail!("Passphrases do not match");
+    }
+    Ok(passphrase)
+}
 
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+fn prompt_passphrase(prompt: &str) -> anyhow::Result<String> {
+    rpassword::prompt_password(prompt).with_context(|| format!("Could not prompt for a passphrase; set {PASSPHRASE_ENV} instead"))
+}
 
-    loop {
-        match swarm.select_next_some().await {
-
-            SwarmEvent::Behaviour(DissonanceEvent::Kademlia(event)) => match event {
-                KademliaEvent::RoutingUpdated{peer,is_new_peer,addresses,bucket_range,old_peer}=>{
-                    // FUTURE: 
-                    // - If `is_new_peer`, persist this peer in your local disk-backed store DONE
-                    //   so the node remembers it after restart (important for bootstrap performance). TODO
-                    // - Use `addresses` to update your local peer-address book (with timestamp). DONE
-                    // - Could check peer reputation/behavior and decide whether to keep it in the routing table. TODOMAYBE
-                    // - If this peer is a new one, trigger Identify protocol to fetch full info. TODO
-                    // - Use peer reputation score to decide whether to keep them. MAYBE
-                    let mut peer_info = peer_store.get_or_create(&peer);
-                    peer_info.addresses = addresses.into_vec();
-                    peer_info.last_seen = SystemTime::now();
-                    println!("[KAD] Routing table updated with the following peer details: {}",peer);
-                },
-                KademliaEvent::InboundRequest{request}=>{
-                    println!("[KAD] Inbound request on DHT");
-                    // FUTURE:
-                    // - Handle `GetRecord` or `PutRecord` requests.
-                    // - You might filter what keys you allow others to store (anti-spam / DoS protection).
-                    // - Optionally encrypt data stored on DHT if privacy is a concern (e.g. store ciphertext only).
-                    // - Consider rate limiting or proof-of-work for writes to mitigate Sybil spam.
-                    },
-                KademliaEvent::OutboundQueryProgressed{id,result,stats,step}=>{
-                    println!("[KAD] Query {} progressed {:?}",id,result);
-                    // FUTURE:
-                    // - Use `result` to know whether a peer lookup or record lookup was successful.
-                    // - If this was a bootstrap query, check `stats` to decide whether to launch more queries.
-                    // - If looking up a peer for message delivery, this is where you connect/send message.
-                    // - Optionally log query performance to tune parallelism or timeouts.
-                },
-                KademliaEvent::UnroutablePeer { peer } => {
-                    println!("[KAD] Unroutable peer detected: {}", peer);
-                    // FUTURE: Could log metrics or attempt to refresh this peer's record.
-                    // Maybe schedule a re-bootstrap or remove it from the routing table if repeated.
-                },
-                KademliaEvent::RoutablePeer { peer, address } => {
-                    println!("[KAD] Routable peer {} detected with address {:?}", peer, address);
-                    // FUTURE: This is a good place to store peer information in a local peer store.DONE
-                    // Can also trigger any queued messages for this peer since it's reachable now.
-                    let peer_info = peer_store.get_or_create(&peer);
-                    peer_info.add_address(address);
-                    println!("[KAD] Routable peer {} added", peer);
+fn open_peer_store(config: &Config) -> anyhow::Result<PeerStore> {
+    if config.storage.ephemeral_peer_store {
+        Ok(PeerStore::new())
+    } else {
+        PeerStore::open(&PeerStore::store_path(&config.data_dir()?))
+    }
+}
+
+fn identity_command(config: &Config, command: &IdentityCommand) -> anyhow::Result<()> {
+    let path = NodeIdentity::identity_path(&config.data_dir()?);
+    match command {
+        IdentityCommand::Show => {
+            if !path.exists() {
+                bail!("No identity at {}; run the node once to create one", path.display());
+            }
+            let identity = load_identity(config)?;
+            let public_key: String = identity.pub_key_bytes().iter().map(|byte| format!("{byte:02x}")).collect();
+            println!("Peer ID:    {}", identity.peer_id());
+            println!("Public key: {public_key}");
+            println!("Stored at:  {}", path.display());
+        },
+        IdentityCommand::Export { out } => {
+            if !path.exists() {
+                bail!("No identity at {}", path.display());
+            }
+            let identity = load_identity(config)?;
+            match out {
+                Some(out) => {
+                    identity.save_plaintext(out)?;
+                    eprintln!("Exported identity {} to {}", identity.peer_id(), out.display());
                 },
-                KademliaEvent::PendingRoutablePeer { peer, address } => {
-                    println!("[KAD] Pending routable peer {} with address {:?}", peer, address);
-                    // FUTURE: This is when the peer is found but not yet fully confirmed.
-                    // You could attempt a direct connection here, or verify Noise handshake before trusting it.
-                    let peer_info = peer_store.get_or_create(&peer);
-                    peer_info.add_address(address);
-                    println!("[KAD] Routable peer {} added", peer);
+                None => println!("{}", identity.to_json()?),
+            }
+        },
+        IdentityCommand::Import { file, force } => {
+            let identity = if NodeIdentity::is_encrypted(file)? {
+                NodeIdentity::load_from_file(file, &existing_passphrase(file)?)?
+            } else {
+                NodeIdentity::from_json(&fs::read_to_string(file).context("Failed to read identity file")?)?
+            };
+            install_identity(&path, &identity, *force)?;
+            println!("Imported identity {}", identity.peer_id());
+        },
+        IdentityCommand::Backup { code } => {
+            if !path.exists() {
+                bail!("No identity at {}", path.display());
+            }
+            let identity = load_identity(config)?;
+            if *code {
+                println!("{}", identity.to_backup_code());
+            } else {
+                println!("{}", identity.to_mnemonic());
+            }
+            eprintln!("Anyone with this backup can impersonate {}; keep it offline", identity.peer_id());
+        },
+        IdentityCommand::Restore { code, force } => {
+            // Check before asking for the phrase so nobody types it in for nothing.
+            if path.exists() && !force {
+                bail!("An identity already exists at {}; pass --force to replace it", path.display());
+            }
+            eprintln!("Enter the {}:", if *code { "backup code" } else { "24-word recovery phrase" });
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input).context("Failed to read from stdin")?;
+            let identity = if *code {
+                NodeIdentity::from_backup_code(&input)?
+            } else {
+                NodeIdentity::from_mnemonic(&input)?
+            };
+            install_identity(&path, &identity, *force)?;
+            println!("Restored identity {}", identity.peer_id());
+        },
+        IdentityCommand::Rotate => {
+            if !path.exists() {
+                bail!("No identity at {}", path.display());
+            }
+            let passphrase = identity_passphrase(&path)?;
+            let previous = NodeIdentity::load_or_create(&path, &passphrase)?;
+            let rotation = NodeIdentity::rotate(&path, &passphrase)?;
+            println!("Rotated identity {} -> {}", previous.peer_id(), rotation.identity.peer_id());
+            println!("Previous identity backed up to {}", rotation.backup.display());
+            println!("Succession certificate will be announced to contacts the next time the node runs");
+        },
+    }
+    Ok(())
+}
+
+/// Stores `identity` at `path`, moving an existing one aside only when `force` is set.
+fn install_identity(path: &Path, identity: &NodeIdentity, force: bool) -> anyhow::Result<()> {
+    if !path.exists() {
+        identity.save_to_file(path, &new_passphrase()?)?;
+    } else if force {
+        let backup = NodeIdentity::replace_file(path, identity, &new_passphrase()?)?;
+        println!("Previous identity backed up to {}", backup.display());
+    } else {
+        bail!("An identity already exists at {}; pass --force to replace it", path.display());
+    }
+    Ok(())
+}
+
+fn profile_command(root: &Path, command: &ProfileCommand) -> anyhow::Result<()> {
+    match command {
+        ProfileCommand::List => {
+            for profile in Profile::list(root)? {
+                let state = if profile.exists() { "ready" } else { "no identity yet" };
+                println!("{}\t{}\t{}", profile.name, state, profile.dir.display());
+            }
+        },
+        ProfileCommand::Create { name } => {
+            let profile = Profile::in_root(root, name)?;
+            if profile.exists() {
+                bail!("Profile `{name}` already exists");
+            }
+            let identity = profile.create(&new_passphrase()?)?;
+            println!("Created profile `{}` with identity {} in {}", name, identity.peer_id(), profile.dir.display());
+        },
+        ProfileCommand::Delete { name, yes } => {
+            let profile = Profile::in_root(root, name)?;
+            if !yes {
+                bail!("Deleting `{name}` destroys its identity; pass --yes to confirm");
+            }
+            profile.delete()?;
+            println!("Deleted profile `{name}`");
+        },
+    }
+    Ok(())
+}
+
+/// Connects to the node already running with this configuration, if one answers. Peer store
+/// edits must go through it, or its next flush would write over them.
+async fn running_node(config: &Config) -> Option<ControlClient> {
+    ControlClient::connect(&config.control_socket().ok()?).await.ok()
+}
+
+async fn peers_command(config: &Config, command: &PeersCommand) -> anyhow::Result<()> {
+    match command {
+        PeersCommand::List => {
+            let peer_store = open_peer_store(config)?;
+            let now = SystemTime::now();
+            for (peer_id, info) in peer_store.list_peers() {
+                let age = now.duration_since(info.last_seen).unwrap_or_default().as_secs();
+                let agent = info.agent_version.as_deref().unwrap_or("unknown");
+                let verified = if peer_store.is_verified(peer_id) { "verified" } else { "unverified" };
+                let score = peer_store.score(peer_id, &config.reputation);
+                println!("{peer_id}\t{verified}\tscore {score:.1}\tlast seen {age}s ago\tagent {agent}\t{:?}", info.addresses);
+            }
+        },
+        PeersCommand::Prune { max_age } => {
+            let removed = match running_node(config).await {
+                Some(mut node) => serde_json::from_value(node.call("prune", json!({ "max_age": max_age })).await?["removed"].take())?,
+                None => {
+                    let mut peer_store = open_peer_store(config)?;
+                    let removed = peer_store.prune_stale(Duration::from_secs(*max_age));
+                    peer_store.flush()?;
+                    removed
                 },
-                KademliaEvent::ModeChanged { new_mode } => {
-                    println!("[KAD] mode changed to {:?}", new_mode);
-                    // FUTURE: Mode can be client or server. 
-                    // If switched to client mode (e.g. behind NAT), maybe trigger bootstrap more often.
-                    // If switched to server mode, you might allow other peers to store records on this node.
+            };
+            println!("Pruned {removed} peers not seen in the last {max_age}s");
+        },
+        PeersCommand::SafetyNumber { peer } => {
+            let identity = load_identity(config)?;
+            let number = safety_number_for(&identity.peer_id(), peer).with_context(|| format!("{peer} does not use an ed25519 key"))?;
+            println!("{number}");
+        },
+        PeersCommand::Verify { peer, undo } => {
+            match running_node(config).await {
+                Some(mut node) => {
+                    node.call(if *undo { "unverify" } else { "verify" }, json!({ "peer": peer })).await?;
                 },
-            },
-            
-            SwarmEvent::Behaviour(DissonanceEvent::Identify(event)) => match event{
-                libp2p::identify::Event::Received { connection_id, peer_id, info } => {
-                    // FUTURE:
-                    // - Store peer's `info` (agent version, supported protocols, listen addresses) DONE
-                    //   in your local peer database to help future connections. DONE
-                    // - Verify the info (e.g., supported protocols match what you expect). TODO
-                    // - Could enforce minimum supported protocol versions here (disconnect otherwise). TODO
-                    // - Might use peer's public key for TOFU (Trust On First Use) logic. TODO
-                    let my_agent = "basic-p2p-node/1.0.0";
-                    let supports_agent = info.agent_version == my_agent;
-                    if supports_agent{
-                    let mut peer_info = PeerInfo::new();
-                    peer_info.last_seen = SystemTime::now();
-                    peer_info.addresses = info.listen_addrs;
-                    peer_info.agent_version = Some(info.agent_version);
-                    peer_info.protocols = info.protocols;
-
-                    peer_store.insert_peer_info(peer_id, peer_info);
-                    println!("[IDENTIFY] Received identity info from peer: {} on connection {:?}", peer_id, connection_id);
+                None => {
+                    let mut peer_store = open_peer_store(config)?;
+                    if *undo {
+                        peer_store.clear_verified(peer);
+                    } else {
+                        peer_store.mark_verified(peer)?;
                     }
+                    peer_store.flush()?;
                 },
-                libp2p::identify::Event::Sent { connection_id, peer_id } => {
-                    println!("[IDENTIFY] Sent our identity info to peer: {} on connection {:?}", peer_id, connection_id);
-                    // FUTURE:
-                    // - Log which peers you have identified to — could track handshake success rate.
-                    // - This is useful to know when you can safely send encrypted messages to this peer.
-                },
-                libp2p::identify::Event::Pushed { connection_id, peer_id, info } => {
-                    // FUTURE:
-                    // - Treat this as an update: refresh your stored info about this peer.
-                    // - Use this to detect network changes (peer changed IP, protocol version, etc.).
-                    // - If `info` looks suspicious (e.g., protocol downgrade attack), trigger security alert.
-                    // let mut peer_info = peer_store.get_or_create(&peer_id);
-                    println!("[IDENTIFY] Received unsolicited identity push from peer: {} on connection {:?}", peer_id, connection_id);                    
-                },
-                libp2p::identify::Event::Error { connection_id, peer_id, error } => {
-                    println!("[IDENTIFY] Error with peer {} on connection {:?}: {:?}", peer_id, connection_id, error);
-                    // FUTURE:
-                    // - Log or count errors for peer reputation system (e.g., disconnect on repeated failures).
-                    // - You may want to retry identification after a delay.
-                    // - Could trigger peer ban if error indicates malicious behaviour.
+            }
+            if *undo {
+                println!("{peer} is no longer verified");
+            } else {
+                println!("Marked {peer} as verified");
+            }
+        },
+        PeersCommand::Unpin { address } => {
+            let unpinned = match running_node(config).await {
+                Some(mut node) => serde_json::from_value(node.call("unpin", json!({ "address": address })).await?["peer"].take())?,
+                None => {
+                    let mut peer_store = open_peer_store(config)?;
+                    let unpinned = peer_store.unpin_address(address);
+                    peer_store.flush()?;
+                    unpinned
                 },
+            };
+            match unpinned {
+                Some(peer) => println!("{address} is no longer pinned to {peer}"),
+                None => println!("{address} was not pinned"),
             }
-                        
-            SwarmEvent::NewListenAddr { address, .. } => {
-                println!("Local node is listening on {address}");
-                println!("Full address: {address}/p2p/{}", swarm.local_peer_id());
-            },
-            SwarmEvent::IncomingConnection { local_addr, send_back_addr, .. } => {
-                println!("Incoming connection from {send_back_addr} on {local_addr}");
-            },
-            SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
-                println!("Connected to peer: {peer_id} via {endpoint:?}");
-            },
-            SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
-                println!("Connection to {peer_id} closed: {cause:?}");
-            },
+        },
+    }
+    Ok(())
+}
 
-            SwarmEvent::Behaviour(DissonanceEvent::Mdns(event)) => match event {
-            libp2p::mdns::Event::Discovered(peers) => {
-                for (peer, addr) in peers {
-                    println!("[MDNS] Discovered peer {} at {:?}", peer, addr);
-                    // FUTURE:
-                    // - Add discovered peer to kademlia for routing table updates
-                    swarm.behaviour_mut().add_kademlia_address(&peer, addr);
-                }
-            },
-            libp2p::mdns::Event::Expired(peers) => {
-                for (peer, addr) in peers {
-                    println!("[MDNS] Peer expired: {} at {:?}", peer, addr);
-                    // FUTURE: Optionally remove peer from routing table if no longer reachable
-                }
+fn gate_command(config: &Config, command: &GateCommand) -> anyhow::Result<()> {
+    let mut list = config.gate_list()?;
+    match command {
+        GateCommand::List => {
+            let now = SystemTime::now();
+            for ban in &list.denied {
+                let expiry = match ban.expires_at {
+                    _ if ban.is_expired(now) => "expired".to_string(),
+                    Some(expires_at) => format!("for {}s", expires_at.saturating_sub(now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs())),
+                    None => "permanent".to_string(),
+                };
+                println!("ban\t{}\t{expiry}\t{}", ban.rule, ban.reason.as_deref().unwrap_or(""));
             }
-        }
-            _ => {
-                //Handle silently
+            for rule in &list.allowed {
+                println!("allow\t{rule}");
             }
-                
+            return Ok(());
+        },
+        GateCommand::Ban { target, duration, reason } => {
+            list.ban(target.clone(), duration.map(Duration::from_secs), reason.clone());
+            println!("Banned {target}");
+        },
+        GateCommand::Unban { target } => {
+            if !list.unban(target) {
+                bail!("{target} is not banned");
+            }
+            println!("Lifted the ban on {target}");
+        },
+        GateCommand::Allow { target } => {
+            list.allow(target.clone());
+            println!("Allowed {target}");
+        },
+        GateCommand::Disallow { target } => {
+            if !list.disallow(target) {
+                bail!("{target} is not on the allow list");
+            }
+            println!("Removed {target} from the allow list");
+        },
+    }
+    list.prune_expired();
+    list.save()
+}
+
+/// Starts a node that only dials out, for the one-shot commands when no node is running.
+fn spawn_one_shot_node(config: &Config) -> anyhow::Result<NodeHandle> {
+    let mut config = config.clone();
+    // A long-running node may already hold the configured ports.
+    config.network.listen_addrs.clear();
+    config.control.enabled = false;
+    Node::spawn(config.clone(), load_identity(&config)?)
+}
+
+/// Dials `address` and remembers the peer it belongs to.
+async fn dial(config: &Config, address: Multiaddr) -> anyhow::Result<()> {
+    if let Some(mut node) = running_node(config).await {
+        let result = tokio::time::timeout(ONE_SHOT_TIMEOUT, node.call("dial", json!({ "address": address })))
+            .await
+            .context("Timed out waiting for the connection")?
+            .with_context(|| format!("Could not dial {address}"))?;
+        let peer_id: PeerId = serde_json::from_value(result["peer_id"].clone())?;
+        println!("Connected to {peer_id} at {address}");
+        return Ok(());
+    }
+
+    let node = spawn_one_shot_node(config)?;
+    let peer_id = tokio::time::timeout(ONE_SHOT_TIMEOUT, node.dial(address.clone()))
+        .await
+        .context("Timed out waiting for the connection")?
+        .with_context(|| format!("Could not dial {address}"))?;
+    println!("Connected to {peer_id} at {address}");
+    // Shutting down flushes the peer store, which now holds the dialed address.
+    node.shutdown().await
+}
+
+/// Sends one chat message and waits for the acknowledgement.
+async fn send(config: &Config, peer: PeerId, addresses: &[Multiaddr], text: &str) -> anyhow::Result<()> {
+    if let Some(mut node) = running_node(config).await {
+        for address in addresses {
+            node.call("add_peer_address", json!({ "peer": peer, "address": address })).await?;
+        }
+        let result = tokio::time::timeout(ONE_SHOT_TIMEOUT, node.call("send", json!({ "peer": peer, "text": text })))
+            .await
+            .context("Timed out waiting for an acknowledgement")??;
+        let id: MessageId = serde_json::from_value(result["message_id"].clone())?;
+        return report_delivery(peer, &id, serde_json::from_value(result["status"].clone())?);
+    }
+
+    let mut addresses = addresses.to_vec();
+    if let Some(info) = open_peer_store(config)?.get(&peer) {
+        addresses.extend(info.addresses.iter().cloned());
+    }
+    if addresses.is_empty() {
+        bail!("No known address for {peer}; pass --address or dial it first");
+    }
+
+    let node = spawn_one_shot_node(config)?;
+    for address in addresses {
+        node.add_peer_address(peer, address).await?;
+    }
+    let ack = tokio::time::timeout(ONE_SHOT_TIMEOUT, node.send(peer, text))
+        .await
+        .context("Timed out waiting for an acknowledgement")??;
+    node.shutdown().await?;
+    report_delivery(peer, &ack.id, ack.status)
+}
+
+fn report_delivery(peer: PeerId, id: &MessageId, status: DeliveryStatus) -> anyhow::Result<()> {
+    match status {
+        DeliveryStatus::Delivered => {
+            println!("Message {id} delivered to {peer}");
+            Ok(())
+        },
+        DeliveryStatus::Rejected(reason) => bail!("Message {id} rejected by {peer}: {reason}"),
+    }
+}
 
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    init_tracing(cli.log_format);
+    let config = cli.load_config()?;
+
+    match &cli.command {
+        None => run(&config, &RunArgs::default()).await?,
+        Some(Command::Run(args)) => run(&config, args).await?,
+        Some(Command::Identity(command)) => identity_command(&config, command)?,
+        Some(Command::Peers(command)) => peers_command(&config, command).await?,
+        Some(Command::Gate(command)) => gate_command(&config, command)?,
+        Some(Command::Profile(command)) => profile_command(&cli.profiles_root()?, command)?,
+        Some(Command::Dial { address }) => dial(&config, address.clone()).await?,
+        Some(Command::Send { peer, address, text }) => send(&config, *peer, address, &text.join(" ")).await?,
+    }
+    Ok(())
+}
+
+async fn run(config: &Config, args: &RunArgs) -> anyhow::Result<()> {
+    tracing::info!("Initialising node identity");
+    let node_identity = if args.ephemeral {
+        NodeIdentity::generate_ephemeral()?
+    } else {
+        load_identity(config)?
+    };
+
+    let node = Node::spawn(config.clone(), node_identity)?;
+
+    let _control = if config.control.enabled {
+        let control = ControlServer::bind(&config.control_socket()?, node.clone())?;
+        tracing::info!("[CONTROL] Listening on {}", control.path().display());
+        Some(control)
+    } else {
+        None
+    };
+
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_open = true;
+    println!("{INPUT_USAGE}");
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return node.shutdown().await,
+
+            line = stdin.next_line(), if stdin_open => match line {
+                // Sends wait for an acknowledgement, so input is handled off the loop.
+                Ok(Some(line)) => {
+                    tokio::spawn(handle_input(node.clone(), line.trim().to_string()));
+                },
+                Ok(None) => stdin_open = false,
+                Err(e) => {
+                    println!("Failed to read from stdin: {e}");
+                    stdin_open = false;
+                }
+            },
+        }
     }
 }
-}
\ No newline at end of file
@@ -1,36 +1,197 @@
-use libp2p::{swarm::{NetworkBehaviour}};
+use libp2p::{swarm::{behaviour::toggle::Toggle, NetworkBehaviour}};
+use libp2p::request_response::{OutboundRequestId, ResponseChannel};
+use libp2p::gossipsub::{MessageId as GossipsubMessageId, PublishError, SubscriptionError};
 
-use crate::network::behaviours::{identify::create_identify, kademlia::get_kademlia, mdns::get_mdns};
+use crate::network::behaviours::{gate::{Gate, GateList}, limits::{get_connection_limits, get_memory_limits}, relay::get_relay_server, autonat::get_autonat, chat::{get_chat, ChatAck, ChatBehaviour, ChatEvent, ChatRequest}, identify::create_identify, kademlia::get_kademlia, mdns::get_mdns, record_store::DissonanceRecordStore, rooms::{get_rooms, RoomEvent, RoomMessage, RoomsBehaviour}, succession::{get_succession, SuccessionAck, SuccessionBehaviour, SuccessionCertificate, SuccessionEvent}};
+use crate::config::Config;
 use super::NodeIdentity;
-use libp2p::{{kad::{Event as KademliaEvent, Behaviour as KademliaBehaviour, store::MemoryStore}}, identify::{Behaviour as IdentifyBehaviour, Event as IdentifyEvent}, mdns::{tokio::Behaviour as MdnsBehaviour, Event as MdnsEvent}};
+use libp2p::{{kad::{Event as KademliaEvent, Behaviour as KademliaBehaviour, ProviderRecord, Quorum, Record, store::RecordStore}}, identify::{Behaviour as IdentifyBehaviour, Event as IdentifyEvent}, mdns::{tokio::Behaviour as MdnsBehaviour, Event as MdnsEvent}};
 
 #[derive(NetworkBehaviour)]
 #[behaviour(to_swarm="DissonanceEvent")]
 pub struct DissonanceBehaviour {
-    kademlia: KademliaBehaviour<MemoryStore>,
+    gate: Gate,
+    limits: libp2p::connection_limits::Behaviour,
+    memory_limits: Toggle<libp2p::memory_connection_limits::Behaviour>,
+    relay_client: libp2p::relay::client::Behaviour,
+    relay_server: Toggle<libp2p::relay::Behaviour>,
+    /// Upgrades relayed connections to direct ones by hole punching.
+    dcutr: libp2p::dcutr::Behaviour,
+    kademlia: KademliaBehaviour<DissonanceRecordStore>,
+    autonat: libp2p::autonat::Behaviour,
     identify: IdentifyBehaviour,
-    mdns: MdnsBehaviour
+    mdns: Toggle<MdnsBehaviour>,
+    chat: ChatBehaviour,
+    rooms: RoomsBehaviour,
+    succession: SuccessionBehaviour,
 }
 
 impl DissonanceBehaviour {
-    pub fn new(identity: &NodeIdentity) -> Self{
-        DissonanceBehaviour { kademlia: get_kademlia(identity), identify: create_identify(identity), mdns: get_mdns(identity) }
+    /// `relay_client` comes from the swarm builder, which pairs it with the relay transport.
+    pub fn new(identity: &NodeIdentity, config: &Config, relay_client: libp2p::relay::client::Behaviour) -> anyhow::Result<Self>{
+        let kad_store = DissonanceRecordStore::new(identity.peer_id(), &config.record_store()?)?;
+        Ok(DissonanceBehaviour {
+            gate: Gate::new(config.gate_list()?),
+            limits: get_connection_limits(&config.limits),
+            memory_limits: get_memory_limits(&config.limits)?,
+            relay_client,
+            relay_server: get_relay_server(identity, &config.relay.server),
+            dcutr: libp2p::dcutr::Behaviour::new(identity.peer_id()),
+            kademlia: get_kademlia(identity, kad_store, &config.kademlia),
+            autonat: get_autonat(identity, &config.autonat),
+            identify: create_identify(identity, &config.identify),
+            mdns: get_mdns(identity, &config.mdns),
+            chat: get_chat(&config.chat),
+            rooms: get_rooms(identity, &config.rooms),
+            succession: get_succession(),
+        })
+    }
+
+    pub fn gate_list(&self) -> &GateList{
+        self.gate.list()
+    }
+
+    /// Applies `change` to the ban list, saves it and closes connections it now refuses.
+    pub fn update_gate<T>(&mut self, change: impl FnOnce(&mut GateList) -> T) -> anyhow::Result<T>{
+        self.gate.update(change)
+    }
+
+    /// Picks up ban list changes made outside the node and drops expired bans.
+    pub fn refresh_gate(&mut self) -> anyhow::Result<()>{
+        let mut list = self.gate.list().clone();
+        let reloaded = list.reload_if_changed()?;
+        if list.prune_expired() > 0 {
+            list.save()?;
+        } else if !reloaded {
+            return Ok(());
+        }
+        self.gate.set_list(list);
+        Ok(())
+    }
+
+    pub fn kademlia_mode(&self) -> libp2p::kad::Mode{
+        self.kademlia.mode()
+    }
+
+    pub fn nat_status(&self) -> libp2p::autonat::NatStatus{
+        self.autonat.nat_status()
     }
 
     pub fn add_kademlia_address(&mut self, peer:&libp2p::PeerId, addr: libp2p::Multiaddr){
         self.kademlia.add_address(peer, addr);
     }
 
-    pub fn bootstrap_kad(&mut self) -> Result<libp2p::kad::QueryId, libp2p::kad::store::Error>{
-        // self.kademlia.bootstrap()
-        todo!()
+    /// Drops `peer` from the routing table; it can be re-added when it is seen again.
+    pub fn remove_kademlia_peer(&mut self, peer: &libp2p::PeerId){
+        self.kademlia.remove_peer(peer);
+    }
+
+    /// Stores a record another peer asked us to keep. Record filtering is on, so Kademlia leaves this to us.
+    pub fn store_inbound_record(&mut self, record: Record) -> libp2p::kad::store::Result<()>{
+        self.kademlia.store_mut().put(record)
+    }
+
+    pub fn store_inbound_provider(&mut self, provider: ProviderRecord) -> libp2p::kad::store::Result<()>{
+        self.kademlia.store_mut().add_provider(provider)
+    }
+
+    pub fn flush_kad_records(&mut self) -> anyhow::Result<()>{
+        self.kademlia.store_mut().flush()
+    }
+
+    /// Re-announces records and provider keys this node published before a restart.
+    /// Returns the number of queries started.
+    pub fn republish_kad_records(&mut self) -> usize{
+        let records = self.kademlia.store_mut().published_records();
+        let provided = self.kademlia.store_mut().provided_keys();
+        let mut started = 0;
+        for record in records {
+            match self.kademlia.put_record(record, Quorum::One) {
+                Ok(_) => started += 1,
+                Err(e) => tracing::warn!("[KAD] Could not republish record: {}", e),
+            }
+        }
+        for key in provided {
+            match self.kademlia.start_providing(key) {
+                Ok(_) => started += 1,
+                Err(e) => tracing::warn!("[KAD] Could not re-announce provider record: {}", e),
+            }
+        }
+        started
+    }
+
+    pub fn bootstrap_kad(&mut self) -> Result<libp2p::kad::QueryId, libp2p::kad::NoKnownPeers>{
+        self.kademlia.bootstrap()
+    }
+
+    pub fn send_chat(&mut self, peer: &libp2p::PeerId, request: ChatRequest) -> OutboundRequestId{
+        self.chat.send_request(peer, request)
+    }
+
+    pub fn acknowledge_chat(&mut self, channel: ResponseChannel<ChatAck>, ack: ChatAck) -> Result<(), ChatAck>{
+        self.chat.send_response(channel, ack)
+    }
+
+    pub fn announce_succession(&mut self, peer: &libp2p::PeerId, certificate: SuccessionCertificate) -> OutboundRequestId{
+        self.succession.send_request(peer, certificate)
+    }
+
+    pub fn acknowledge_succession(&mut self, channel: ResponseChannel<SuccessionAck>, ack: SuccessionAck) -> Result<(), SuccessionAck>{
+        self.succession.send_response(channel, ack)
+    }
+
+    /// Stores `certificate` locally and in the DHT under the previous peer id.
+    pub fn publish_succession(&mut self, certificate: &SuccessionCertificate) -> anyhow::Result<()>{
+        let key = SuccessionCertificate::record_key(&certificate.previous()?);
+        let record = Record::new(key, serde_json::to_vec(certificate)?);
+        self.kademlia.put_record(record, Quorum::One)?;
+        Ok(())
+    }
+
+    /// Looks for a succession certificate published for `previous`.
+    pub fn find_succession(&mut self, previous: &libp2p::PeerId) -> libp2p::kad::QueryId{
+        self.kademlia.get_record(SuccessionCertificate::record_key(previous))
+    }
+
+    pub fn join_room(&mut self, room: &str) -> Result<bool, SubscriptionError>{
+        self.rooms.join(room)
+    }
+
+    pub fn leave_room(&mut self, room: &str) -> bool{
+        self.rooms.leave(room)
+    }
+
+    pub fn publish_room_message(&mut self, message: &RoomMessage) -> Result<GossipsubMessageId, PublishError>{
+        self.rooms.publish(message)
+    }
+
+    pub fn joined_rooms(&self) -> Vec<String>{
+        self.rooms.rooms()
+    }
+
+    pub fn room_members(&self, room: &str) -> Vec<libp2p::PeerId>{
+        self.rooms.members(room)
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum DissonanceEvent {
     Kademlia(KademliaEvent),
+    Autonat(libp2p::autonat::Event),
     Identify(IdentifyEvent),
-    Mdns(MdnsEvent)
+    Mdns(MdnsEvent),
+    Chat(ChatEvent),
+    Room(RoomEvent),
+    Succession(SuccessionEvent),
+    RelayClient(libp2p::relay::client::Event),
+    RelayServer(libp2p::relay::Event),
+    Dcutr(libp2p::dcutr::Event),
+}
+
+impl From<std::convert::Infallible> for DissonanceEvent {
+    fn from(value: std::convert::Infallible) -> Self {
+        match value {}
+    }
 }
 
 impl From<KademliaEvent> for DissonanceEvent {
@@ -51,3 +212,44 @@ impl From<MdnsEvent> for DissonanceEvent {
     }
 }
 
+impl From<ChatEvent> for DissonanceEvent {
+    fn from(value: ChatEvent) -> Self {
+        DissonanceEvent::Chat(value)
+    }
+}
+
+impl From<RoomEvent> for DissonanceEvent {
+    fn from(value: RoomEvent) -> Self {
+        DissonanceEvent::Room(value)
+    }
+}
+
+impl From<SuccessionEvent> for DissonanceEvent {
+    fn from(value: SuccessionEvent) -> Self {
+        DissonanceEvent::Succession(value)
+    }
+}
+
+impl From<libp2p::relay::client::Event> for DissonanceEvent {
+    fn from(value: libp2p::relay::client::Event) -> Self {
+        DissonanceEvent::RelayClient(value)
+    }
+}
+
+impl From<libp2p::relay::Event> for DissonanceEvent {
+    fn from(value: libp2p::relay::Event) -> Self {
+        DissonanceEvent::RelayServer(value)
+    }
+}
+
+impl From<libp2p::dcutr::Event> for DissonanceEvent {
+    fn from(value: libp2p::dcutr::Event) -> Self {
+        DissonanceEvent::Dcutr(value)
+    }
+}
+
+impl From<libp2p::autonat::Event> for DissonanceEvent {
+    fn from(value: libp2p::autonat::Event) -> Self {
+        DissonanceEvent::Autonat(value)
+    }
+}
@@ -0,0 +1,18 @@
+use libp2p::autonat::{Behaviour as AutonatBehaviour, Config as AutonatConfig};
+
+use crate::config::AutonatOptions;
+use crate::NodeIdentity;
+
+/// Probes our own reachability and answers probes from other peers. Addresses that peers manage
+/// to dial back are confirmed as external, which is what moves Kademlia into server mode.
+pub fn get_autonat(identity: &NodeIdentity, options: &AutonatOptions) -> AutonatBehaviour {
+    let config = AutonatConfig {
+        boot_delay: options.boot_delay,
+        retry_interval: options.retry_interval,
+        refresh_interval: options.refresh_interval,
+        confidence_max: options.confidence_max,
+        only_global_ips: options.only_global_ips,
+        ..AutonatConfig::default()
+    };
+    AutonatBehaviour::new(identity.peer_id(), config)
+}
@@ -0,0 +1,168 @@
+use std::{fmt, time::SystemTime};
+
+use libp2p::{request_response::{self, json, ProtocolSupport}, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+
+use crate::config::ChatOptions;
+use crate::e2e::{E2eError, E2eKeys, Envelope};
+
+pub const CHAT_PROTOCOL: StreamProtocol = StreamProtocol::new("/dissonance/chat/1.0.0");
+
+pub type ChatBehaviour = json::Behaviour<ChatRequest, ChatAck>;
+pub type ChatEvent = request_response::Event<ChatRequest, ChatAck>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MessageId(String);
+
+impl MessageId {
+    pub fn random() -> Self {
+        MessageId(format!("{:032x}", rand::random::<u128>()))
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub id: MessageId,
+    pub sender: PeerId,
+    pub timestamp: SystemTime,
+    pub body: String,
+}
+
+impl ChatMessage {
+    pub fn new(sender: PeerId, body: impl Into<String>) -> Self {
+        ChatMessage { id: MessageId::random(), sender, timestamp: SystemTime::now(), body: body.into() }
+    }
+
+    /// The sender field is self-reported, so it must match the peer the request arrived from.
+    pub fn is_from(&self, peer: &PeerId) -> bool {
+        &self.sender == peer
+    }
+
+    pub fn seal(&self, keys: &E2eKeys, recipient: &PeerId) -> Result<ChatRequest, E2eError> {
+        let plaintext = serde_json::to_vec(self).expect("chat messages always serialize");
+        Ok(ChatRequest { id: self.id.clone(), envelope: keys.seal(recipient, &plaintext)? })
+    }
+}
+
+/// What actually travels on the wire: the message id stays in the clear so it can be acknowledged
+/// even when the envelope cannot be opened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub id: MessageId,
+    pub envelope: Envelope,
+}
+
+impl ChatRequest {
+    pub fn open(&self, keys: &E2eKeys) -> Result<ChatMessage, E2eError> {
+        let plaintext = keys.open(&self.envelope)?;
+        let message: ChatMessage = serde_json::from_slice(&plaintext).map_err(|_| E2eError::Decryption)?;
+        // The envelope sender is authenticated by the static key exchange, the inner fields are not.
+        if message.id != self.id || !message.is_from(&self.envelope.sender) {
+            return Err(E2eError::Decryption);
+        }
+        Ok(message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    Delivered,
+    Rejected(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatAck {
+    pub id: MessageId,
+    pub status: DeliveryStatus,
+    pub received_at: SystemTime,
+}
+
+impl ChatAck {
+    pub fn delivered(id: &MessageId) -> Self {
+        ChatAck { id: id.clone(), status: DeliveryStatus::Delivered, received_at: SystemTime::now() }
+    }
+
+    pub fn rejected(id: &MessageId, reason: impl Into<String>) -> Self {
+        ChatAck { id: id.clone(), status: DeliveryStatus::Rejected(reason.into()), received_at: SystemTime::now() }
+    }
+}
+
+pub fn get_chat(options: &ChatOptions) -> ChatBehaviour {
+    let chat_config = request_response::Config::default()
+        .with_request_timeout(options.request_timeout);
+
+    ChatBehaviour::new([(CHAT_PROTOCOL, ProtocolSupport::Full)], chat_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NodeIdentity;
+
+    #[test]
+    fn test_message_ids_are_unique() {
+        let sender = PeerId::random();
+        let first = ChatMessage::new(sender, "hello");
+        let second = ChatMessage::new(sender, "hello");
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.id.to_string().len(), 32);
+    }
+
+    #[test]
+    fn test_chat_message_roundtrip() {
+        let message = ChatMessage::new(PeerId::random(), "hi there");
+        let json = serde_json::to_string(&message).expect("Failed to serialize message");
+        let decoded: ChatMessage = serde_json::from_str(&json).expect("Failed to parse message");
+        assert_eq!(decoded.id, message.id);
+        assert_eq!(decoded.sender, message.sender);
+        assert_eq!(decoded.timestamp, message.timestamp);
+        assert_eq!(decoded.body, "hi there");
+    }
+
+    #[test]
+    fn test_ack_references_message() {
+        let sender = PeerId::random();
+        let message = ChatMessage::new(sender, "ping");
+        assert!(message.is_from(&sender));
+        assert!(!message.is_from(&PeerId::random()));
+
+        let ack = ChatAck::delivered(&message.id);
+        assert_eq!(ack.id, message.id);
+        assert_eq!(ack.status, DeliveryStatus::Delivered);
+
+        let rejected = ChatAck::rejected(&message.id, "spoofed sender");
+        assert_eq!(rejected.status, DeliveryStatus::Rejected("spoofed sender".to_string()));
+    }
+
+    #[test]
+    fn test_sealed_request_roundtrip() {
+        let alice = NodeIdentity::generate_ephemeral().unwrap();
+        let bob = NodeIdentity::generate_ephemeral().unwrap();
+        let (alice_keys, bob_keys) = (E2eKeys::from_identity(&alice), E2eKeys::from_identity(&bob));
+
+        let message = ChatMessage::new(alice.peer_id(), "for bob only");
+        let request = message.seal(&alice_keys, &bob.peer_id()).unwrap();
+        assert!(!serde_json::to_string(&request).unwrap().contains("for bob only"));
+
+        let opened = request.open(&bob_keys).unwrap();
+        assert_eq!(opened.body, "for bob only");
+        assert_eq!(opened.sender, alice.peer_id());
+    }
+
+    #[test]
+    fn test_open_rejects_forged_inner_sender() {
+        let alice = NodeIdentity::generate_ephemeral().unwrap();
+        let bob = NodeIdentity::generate_ephemeral().unwrap();
+        let (alice_keys, bob_keys) = (E2eKeys::from_identity(&alice), E2eKeys::from_identity(&bob));
+
+        let forged = ChatMessage::new(PeerId::random(), "pretending");
+        let request = forged.seal(&alice_keys, &bob.peer_id()).unwrap();
+        assert_eq!(request.open(&bob_keys).unwrap_err(), E2eError::Decryption);
+    }
+}
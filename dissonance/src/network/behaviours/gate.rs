@@ -0,0 +1,414 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    fmt, fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    task::{Context, Poll, Waker},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context as _, Result};
+use ipnet::IpNet;
+use libp2p::{
+    core::{transport::PortUse, Endpoint},
+    multiaddr::Protocol,
+    swarm::{
+        dummy, CloseConnection, ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+        THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::store::write_atomic;
+
+pub const GATE_FILE: &str = "ban-list.json";
+
+/// What a ban or allow entry applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GateRule {
+    Peer(PeerId),
+    /// An IP address or CIDR range, matched against the first IP in a connection's address.
+    Network(IpNet),
+    /// A multiaddr prefix, e.g. `/ip4/203.0.113.7/tcp/4001` or `/dns4/example.com`.
+    Address(Multiaddr),
+}
+
+impl GateRule {
+    fn matches_peer(&self, peer: &PeerId) -> bool {
+        matches!(self, GateRule::Peer(banned) if banned == peer)
+    }
+
+    fn matches_address(&self, address: &Multiaddr) -> bool {
+        match self {
+            GateRule::Peer(peer) => address.iter().any(|protocol| protocol == Protocol::P2p(*peer)),
+            GateRule::Network(network) => first_ip(address).is_some_and(|ip| network.contains(&ip)),
+            GateRule::Address(prefix) => {
+                let mut components = address.iter();
+                prefix.iter().all(|expected| components.next() == Some(expected))
+            },
+        }
+    }
+}
+
+fn first_ip(address: &Multiaddr) -> Option<IpAddr> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })
+}
+
+impl FromStr for GateRule {
+    type Err = anyhow::Error;
+
+    /// Accepts a peer id, an IP address or CIDR range, or a multiaddr.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(peer) = s.parse() {
+            return Ok(GateRule::Peer(peer));
+        }
+        if let Ok(network) = s.parse() {
+            return Ok(GateRule::Network(network));
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(GateRule::Network(ip.into()));
+        }
+        match s.parse() {
+            Ok(address) => Ok(GateRule::Address(address)),
+            Err(_) => bail!("`{s}` is not a peer id, IP address, CIDR range or multiaddr"),
+        }
+    }
+}
+
+impl fmt::Display for GateRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GateRule::Peer(peer) => peer.fmt(f),
+            GateRule::Network(network) => network.fmt(f),
+            GateRule::Address(address) => address.fmt(f),
+        }
+    }
+}
+
+impl Serialize for GateRule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for GateRule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    pub rule: GateRule,
+    /// Seconds since the Unix epoch; `None` bans until lifted.
+    pub expires_at: Option<u64>,
+    pub reason: Option<String>,
+}
+
+impl Ban {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| UNIX_EPOCH + Duration::from_secs(expires_at) <= now)
+    }
+}
+
+/// Persistent deny and allow lists. Allow entries win, so a single address can be let through
+/// a banned range.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct GateList {
+    #[serde(default)]
+    pub denied: Vec<Ban>,
+    #[serde(default)]
+    pub allowed: Vec<GateRule>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    modified: Option<SystemTime>,
+}
+
+impl GateList {
+    pub fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(GATE_FILE)
+    }
+
+    /// Loads the list kept at `path`, or starts an empty one that will be saved there.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut list = if path.exists() {
+            let content = fs::read_to_string(path).context("Failed to read ban list")?;
+            serde_json::from_str(&content).context("Failed to parse ban list")?
+        } else {
+            Self::default()
+        };
+        list.path = Some(path.to_path_buf());
+        list.modified = modified_at(path);
+        Ok(list)
+    }
+
+    /// Writes the list back to where it was opened from; in-memory lists are not saved.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let content = serde_json::to_vec_pretty(self).context("Failed to serialize ban list")?;
+        write_atomic(path, &content).context("Failed to write ban list")?;
+        self.modified = modified_at(path);
+        Ok(())
+    }
+
+    /// Reloads the list if its file was changed by someone else, e.g. the CLI.
+    /// Returns whether it was reloaded.
+    pub fn reload_if_changed(&mut self) -> Result<bool> {
+        let Some(path) = self.path.clone() else {
+            return Ok(false);
+        };
+        if modified_at(&path) == self.modified {
+            return Ok(false);
+        }
+        *self = Self::open(&path)?;
+        Ok(true)
+    }
+
+    /// Bans `rule`, for `duration` if given. Replaces an earlier ban on the same rule.
+    pub fn ban(&mut self, rule: GateRule, duration: Option<Duration>, reason: Option<String>) {
+        let expires_at = duration.map(|duration| (SystemTime::now() + duration).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+        self.denied.retain(|ban| ban.rule != rule);
+        self.denied.push(Ban { rule, expires_at, reason });
+    }
+
+    /// Returns whether `rule` was banned.
+    pub fn unban(&mut self, rule: &GateRule) -> bool {
+        let before = self.denied.len();
+        self.denied.retain(|ban| ban.rule != *rule);
+        self.denied.len() != before
+    }
+
+    pub fn allow(&mut self, rule: GateRule) {
+        if !self.allowed.contains(&rule) {
+            self.allowed.push(rule);
+        }
+    }
+
+    /// Returns whether `rule` was allowed.
+    pub fn disallow(&mut self, rule: &GateRule) -> bool {
+        let before = self.allowed.len();
+        self.allowed.retain(|allowed| allowed != rule);
+        self.allowed.len() != before
+    }
+
+    /// Drops expired bans. Returns how many were dropped.
+    pub fn prune_expired(&mut self) -> usize {
+        let now = SystemTime::now();
+        let before = self.denied.len();
+        self.denied.retain(|ban| !ban.is_expired(now));
+        before - self.denied.len()
+    }
+
+    /// The active ban that applies to a connection with `peer` and/or at `address`, if any.
+    pub fn ban_for(&self, peer: Option<&PeerId>, address: Option<&Multiaddr>) -> Option<&Ban> {
+        let matches = |rule: &GateRule| {
+            peer.is_some_and(|peer| rule.matches_peer(peer)) || address.is_some_and(|address| rule.matches_address(address))
+        };
+        if self.allowed.iter().any(matches) {
+            return None;
+        }
+        let now = SystemTime::now();
+        self.denied.iter().find(|ban| !ban.is_expired(now) && matches(&ban.rule))
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Reason a connection was refused by the gate.
+#[derive(Debug)]
+pub struct Banned(pub GateRule);
+
+impl fmt::Display for Banned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection refused by ban on {}", self.0)
+    }
+}
+
+impl std::error::Error for Banned {}
+
+/// Refuses connections matching the ban list. Addresses are checked before any handshake; peer
+/// ids as soon as they are known, which for inbound connections is right after the handshake.
+pub struct Gate {
+    list: GateList,
+    connections: HashMap<ConnectionId, (PeerId, Multiaddr)>,
+    close_connections: VecDeque<PeerId>,
+    waker: Option<Waker>,
+}
+
+impl Gate {
+    pub fn new(list: GateList) -> Self {
+        Gate { list, connections: HashMap::new(), close_connections: VecDeque::new(), waker: None }
+    }
+
+    pub fn list(&self) -> &GateList {
+        &self.list
+    }
+
+    /// Replaces the list and closes open connections it now refuses.
+    pub fn set_list(&mut self, list: GateList) {
+        self.list = list;
+        let refused: Vec<PeerId> = self.connections.values()
+            .filter(|(peer, address)| self.list.ban_for(Some(peer), Some(address)).is_some())
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in refused {
+            if !self.close_connections.contains(&peer) {
+                self.close_connections.push_back(peer);
+            }
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Changes the list through `change`, saves it and closes connections it now refuses.
+    pub fn update<T>(&mut self, change: impl FnOnce(&mut GateList) -> T) -> Result<T> {
+        let mut list = self.list.clone();
+        let result = change(&mut list);
+        list.save()?;
+        self.set_list(list);
+        Ok(result)
+    }
+
+    fn enforce(&self, peer: Option<&PeerId>, address: Option<&Multiaddr>) -> Result<(), ConnectionDenied> {
+        match self.list.ban_for(peer, address) {
+            Some(ban) => Err(ConnectionDenied::new(Banned(ban.rule.clone()))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl NetworkBehaviour for Gate {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.enforce(None, Some(remote_addr))
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.enforce(Some(&peer), Some(remote_addr))?;
+        self.connections.insert(connection_id, (peer, remote_addr.clone()));
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        _: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if let Some(peer) = peer {
+            self.enforce(Some(&peer), None)?;
+        }
+        // Other behaviours may still add addresses, so only a dial whose every address is banned is refused here.
+        if let Some(first) = addresses.first()
+            && addresses.iter().all(|address| self.list.ban_for(None, Some(address)).is_some()) {
+            self.enforce(None, Some(first))?;
+        }
+        Ok(vec![])
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        _: Endpoint,
+        _: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.enforce(Some(&peer), Some(addr))?;
+        self.connections.insert(connection_id, (peer, addr.clone()));
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(ConnectionClosed { connection_id, .. }) = event {
+            self.connections.remove(&connection_id);
+        }
+    }
+
+    fn on_connection_handler_event(&mut self, _: PeerId, _: ConnectionId, event: THandlerOutEvent<Self>) {
+        match event {}
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        if let Some(peer_id) = self.close_connections.pop_front() {
+            return Poll::Ready(ToSwarm::CloseConnection { peer_id, connection: CloseConnection::All });
+        }
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rules_parse_and_match() {
+        let peer = PeerId::random();
+        let address: Multiaddr = format!("/ip4/203.0.113.7/tcp/4001/p2p/{peer}").parse().unwrap();
+
+        for rule in [peer.to_string(), "203.0.113.0/24".into(), "203.0.113.7".into(), "/ip4/203.0.113.7".into()] {
+            let rule: GateRule = rule.parse().unwrap();
+            assert!(rule.matches_address(&address), "{rule} should match {address}");
+            assert_eq!(rule.to_string().parse::<GateRule>().unwrap(), rule);
+        }
+        for rule in ["198.51.100.0/24", "/ip4/203.0.113.7/tcp/4002"] {
+            assert!(!rule.parse::<GateRule>().unwrap().matches_address(&address), "{rule} should not match {address}");
+        }
+        assert!("not a target".parse::<GateRule>().is_err());
+    }
+
+    #[test]
+    fn test_bans_expire_and_allow_entries_win() {
+        let temp = tempdir().unwrap();
+        let path = GateList::path(temp.path());
+        let (banned, other) = (PeerId::random(), PeerId::random());
+        let inside: Multiaddr = "/ip4/10.1.2.3/tcp/4001".parse().unwrap();
+        let allowed: Multiaddr = "/ip4/10.1.2.4/tcp/4001".parse().unwrap();
+
+        let mut list = GateList::open(&path).unwrap();
+        list.ban(GateRule::Peer(banned), None, Some("spam".into()));
+        list.ban("10.0.0.0/8".parse().unwrap(), None, None);
+        list.allow("10.1.2.4".parse().unwrap());
+        list.ban(GateRule::Peer(other), Some(Duration::ZERO), None);
+        list.save().unwrap();
+
+        let mut list = GateList::open(&path).unwrap();
+        assert_eq!(list.ban_for(Some(&banned), None).unwrap().reason.as_deref(), Some("spam"));
+        assert!(list.ban_for(None, Some(&inside)).is_some());
+        assert!(list.ban_for(None, Some(&allowed)).is_none());
+        assert!(list.ban_for(Some(&other), None).is_none(), "An expired ban no longer applies");
+        assert_eq!(list.prune_expired(), 1);
+
+        assert!(list.unban(&GateRule::Peer(banned)));
+        assert!(list.ban_for(Some(&banned), None).is_none());
+    }
+}
@@ -1,17 +1,90 @@
-use std::time::Duration;
-
 use libp2p::identify::{Behaviour as IdentifyBehaviour, Config as IdentifyConfig};
+use semver::Version;
 
+use crate::config::IdentifyOptions;
 use crate::NodeIdentity;
 
-pub fn create_identify(identity: &NodeIdentity) -> IdentifyBehaviour{
+/// Identify protocol version of the Dissonance network. Its major number changes whenever the
+/// wire protocols change incompatibly.
+pub const PROTOCOL_VERSION: &str = "/dissonance/1.0.0";
+
+pub const AGENT_NAME: &str = "dissonance";
+
+/// Oldest release whose wire protocols this one still speaks.
+pub const MIN_PEER_VERSION: Version = Version::new(0, 1, 0);
+
+/// First release this one is known not to understand; before 1.0 every minor release may break the protocol.
+pub const MAX_PEER_VERSION: Version = Version::new(0, 2, 0);
+
+/// `dissonance/<crate version>`, as advertised over Identify.
+pub fn agent_version() -> String {
+    format!("{AGENT_NAME}/{}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Whether a peer that identified itself can be talked to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compatibility {
+    Compatible(Version),
+    /// Not a Dissonance node, e.g. a plain libp2p DHT server; only useful for routing.
+    Foreign,
+    Incompatible(String),
+}
+
+pub fn check_compatibility(protocol_version: &str, agent_version: &str) -> Compatibility {
+    let Some(protocol) = protocol_version.strip_prefix("/dissonance/") else {
+        return Compatibility::Foreign;
+    };
+    let ours = PROTOCOL_VERSION.strip_prefix("/dissonance/").and_then(|version| Version::parse(version).ok()).expect("valid protocol version");
+    match Version::parse(protocol) {
+        Ok(theirs) if theirs.major == ours.major => {},
+        Ok(theirs) => return Compatibility::Incompatible(format!("protocol {theirs} is not compatible with {ours}")),
+        Err(_) => return Compatibility::Incompatible(format!("malformed protocol version `{protocol_version}`")),
+    }
+
+    let version = agent_version
+        .strip_prefix(AGENT_NAME)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .and_then(|version| Version::parse(version).ok());
+    match version {
+        Some(version) if version < MIN_PEER_VERSION => Compatibility::Incompatible(format!("version {version} is older than {MIN_PEER_VERSION}")),
+        Some(version) if version >= MAX_PEER_VERSION => Compatibility::Incompatible(format!("version {version} is newer than this node supports")),
+        Some(version) => Compatibility::Compatible(version),
+        None => Compatibility::Incompatible(format!("unrecognised agent `{agent_version}`")),
+    }
+}
+
+pub fn create_identify(identity: &NodeIdentity, options: &IdentifyOptions) -> IdentifyBehaviour{
 
     let keypair = identity.to_lp2p_keypair().unwrap();
-    let identify_config = IdentifyConfig::new("/basic-p2p/1.0.0".to_string(), keypair.public())
-    .with_agent_version("basic-p2p-node/0.1.0".to_string())
+    let identify_config = IdentifyConfig::new(PROTOCOL_VERSION.to_string(), keypair.public())
+    .with_agent_version(agent_version())
     .with_push_listen_addr_updates(true)
-    .with_interval(Duration::from_secs(30));
+    .with_interval(options.interval);
+
+    IdentifyBehaviour::new(identify_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_own_version_is_compatible() {
+        assert!(matches!(check_compatibility(PROTOCOL_VERSION, &agent_version()), Compatibility::Compatible(_)));
+        assert_eq!(check_compatibility("/dissonance/1.4.2", "dissonance/0.1.9"), Compatibility::Compatible(Version::new(0, 1, 9)));
+    }
 
-    let identify = IdentifyBehaviour::new(identify_config);
-    identify
-}
\ No newline at end of file
+    #[test]
+    fn test_versions_outside_the_supported_range_are_rejected() {
+        assert_eq!(check_compatibility("/ipfs/0.1.0", "kubo/0.30.0"), Compatibility::Foreign);
+        for (protocol, agent) in [
+            ("/dissonance/2.0.0", "dissonance/0.1.0"),
+            ("/dissonance/1.0.0", "dissonance/0.0.9"),
+            ("/dissonance/1.0.0", "dissonance/0.2.0"),
+            ("/dissonance/1.0.0", "basic-p2p-node/0.1.0"),
+            ("/dissonance/one", "dissonance/0.1.0"),
+        ] {
+            assert!(matches!(check_compatibility(protocol, agent), Compatibility::Incompatible(_)), "{protocol} {agent} should be rejected");
+        }
+    }
+}
@@ -1,21 +1,30 @@
-use std::time::Duration;
-
-use libp2p::{kad::{store::MemoryStore as KademliaStore, Behaviour as KademliaBehaviour, Config as KademliaConfig,
-    Mode as KademliaMode
+use libp2p::{kad::{Behaviour as KademliaBehaviour, Config as KademliaConfig,
+    Mode as KademliaMode, StoreInserts
 }};
 
+use crate::config::{DhtMode, KademliaOptions};
+use crate::network::behaviours::record_store::DissonanceRecordStore as KademliaStore;
 use crate::NodeIdentity;
 
-pub fn get_kademlia(identity: &NodeIdentity) -> KademliaBehaviour<KademliaStore>{
+pub fn get_kademlia(identity: &NodeIdentity, kad_store: KademliaStore, options: &KademliaOptions) -> KademliaBehaviour<KademliaStore>{
 
-    let kad_store = KademliaStore::new(identity.peer_id());
     let mut kad_config = KademliaConfig::default();
-    kad_config.set_query_timeout(Duration::from_secs(20));
-    kad_config.set_replication_factor(20.try_into().unwrap());
-    kad_config.set_max_packet_size(16*1024);
+    kad_config.set_query_timeout(options.query_timeout);
+    kad_config.set_replication_factor(options.replication_factor);
+    kad_config.set_max_packet_size(options.max_packet_size);
+    // Bootstrapping is scheduled by `network::bootstrap::Bootstrapper`, which adds backoff and events.
+    kad_config.set_periodic_bootstrap_interval(None);
+    // Inbound records are stored by the node, which first checks the sender's reputation.
+    kad_config.set_record_filtering(StoreInserts::FilterBoth);
 
     let mut kademlia = KademliaBehaviour::with_config(identity.peer_id(), kad_store, kad_config);
-    kademlia.set_mode(Some(KademliaMode::Server));
+    // With no mode set, Kademlia serves while an external address is confirmed and is a client otherwise,
+    // so unreachable nodes do not end up in other peers' routing tables.
+    kademlia.set_mode(match options.mode {
+        DhtMode::Auto => None,
+        DhtMode::Client => Some(KademliaMode::Client),
+        DhtMode::Server => Some(KademliaMode::Server),
+    });
 
     kademlia
 }
\ No newline at end of file
@@ -0,0 +1,31 @@
+use anyhow::{bail, Result};
+use libp2p::{
+    connection_limits::{Behaviour as ConnectionLimitsBehaviour, ConnectionLimits},
+    memory_connection_limits::Behaviour as MemoryLimitsBehaviour,
+    swarm::behaviour::toggle::Toggle,
+};
+
+use crate::config::LimitsOptions;
+
+pub fn get_connection_limits(options: &LimitsOptions) -> ConnectionLimitsBehaviour {
+    let limits = ConnectionLimits::default()
+        .with_max_pending_incoming(options.max_pending_incoming)
+        .with_max_pending_outgoing(options.max_pending_outgoing)
+        .with_max_established_incoming(options.max_established_incoming)
+        .with_max_established_outgoing(options.max_established_outgoing)
+        .with_max_established(options.max_established_total)
+        .with_max_established_per_peer(options.max_established_per_peer);
+    ConnectionLimitsBehaviour::new(limits)
+}
+
+/// Refuses new connections while the process uses more memory than allowed. An absolute
+/// `max_memory_bytes` takes precedence over `max_memory_fraction`; with neither set the guard is off.
+pub fn get_memory_limits(options: &LimitsOptions) -> Result<Toggle<MemoryLimitsBehaviour>> {
+    let behaviour = match (options.max_memory_bytes, options.max_memory_fraction) {
+        (Some(bytes), _) => Some(MemoryLimitsBehaviour::with_max_bytes(bytes)),
+        (None, Some(fraction)) if fraction > 0.0 && fraction <= 1.0 => Some(MemoryLimitsBehaviour::with_max_percentage(fraction)),
+        (None, Some(fraction)) => bail!("max_memory_fraction must be in (0, 1], got {fraction}"),
+        (None, None) => None,
+    };
+    Ok(Toggle::from(behaviour))
+}
@@ -1,9 +1,17 @@
-use libp2p::{mdns::{tokio::Behaviour as MdnsBehaviour, Config as MdnsConfig}};
+use libp2p::{mdns::{tokio::Behaviour as MdnsBehaviour, Config as MdnsConfig}, swarm::behaviour::toggle::Toggle};
 
+use crate::config::MdnsOptions;
 use crate::NodeIdentity;
 
-pub fn get_mdns(identity: &NodeIdentity)  -> MdnsBehaviour{
-    let mdns_config = MdnsConfig::default();
-    let mdns_behaviour = MdnsBehaviour::new(mdns_config, identity.peer_id()).unwrap();
-    mdns_behaviour
+/// Returns a disabled behaviour when `options.enabled` is false, e.g. on networks that block multicast.
+pub fn get_mdns(identity: &NodeIdentity, options: &MdnsOptions) -> Toggle<MdnsBehaviour>{
+    if !options.enabled {
+        return Toggle::from(None);
+    }
+    let mdns_config = MdnsConfig {
+        ttl: options.ttl,
+        query_interval: options.query_interval,
+        enable_ipv6: options.enable_ipv6,
+    };
+    Toggle::from(Some(MdnsBehaviour::new(mdns_config, identity.peer_id()).unwrap()))
 }
\ No newline at end of file
@@ -1,6 +1,21 @@
 pub mod kademlia;
 
+pub mod record_store;
+
 pub mod identify;
 
 pub mod mdns;
 
+pub mod chat;
+
+pub mod rooms;
+
+pub mod succession;
+
+pub mod gate;
+
+pub mod limits;
+
+pub mod relay;
+
+pub mod autonat;
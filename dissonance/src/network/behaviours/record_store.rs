@@ -0,0 +1,345 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use libp2p::{
+    kad::{
+        store::{self, MemoryStore, MemoryStoreConfig, RecordStore},
+        ProviderRecord, Record, RecordKey,
+    },
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::default_data_dir;
+use crate::store::write_atomic;
+
+pub const RECORD_STORE_SCHEMA_VERSION: u32 = 1;
+pub const RECORD_STORE_FILE: &str = "kad-records.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordStoreBackend {
+    Memory,
+    Disk,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordStoreConfig {
+    pub backend: RecordStoreBackend,
+    /// Overrides `default_disk_path` for the disk backend.
+    pub path: Option<PathBuf>,
+    pub max_records: usize,
+    pub max_value_bytes: usize,
+    pub max_providers_per_key: usize,
+    pub max_provided_keys: usize,
+}
+
+impl RecordStoreConfig {
+    pub fn memory() -> Self {
+        let limits = MemoryStoreConfig::default();
+        RecordStoreConfig {
+            backend: RecordStoreBackend::Memory,
+            path: None,
+            max_records: limits.max_records,
+            max_value_bytes: limits.max_value_bytes,
+            max_providers_per_key: limits.max_providers_per_key,
+            max_provided_keys: limits.max_provided_keys,
+        }
+    }
+
+    pub fn disk(path: impl Into<PathBuf>) -> Self {
+        RecordStoreConfig { backend: RecordStoreBackend::Disk, path: Some(path.into()), ..Self::memory() }
+    }
+
+    pub fn default_disk_path() -> Result<PathBuf> {
+        Ok(default_data_dir()?.join(RECORD_STORE_FILE))
+    }
+
+    /// The file records are persisted to, or `None` for the memory backend.
+    pub fn resolved_path(&self) -> Result<Option<PathBuf>> {
+        match (self.backend, &self.path) {
+            (RecordStoreBackend::Memory, _) => Ok(None),
+            (RecordStoreBackend::Disk, Some(path)) => Ok(Some(path.clone())),
+            (RecordStoreBackend::Disk, None) => Self::default_disk_path().map(Some),
+        }
+    }
+
+    pub fn limits(&self) -> MemoryStoreConfig {
+        MemoryStoreConfig {
+            max_records: self.max_records,
+            max_value_bytes: self.max_value_bytes,
+            max_providers_per_key: self.max_providers_per_key,
+            max_provided_keys: self.max_provided_keys,
+        }
+    }
+}
+
+impl Default for RecordStoreConfig {
+    fn default() -> Self {
+        Self::memory()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    publisher: Option<PeerId>,
+    expires: Option<SystemTime>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProviderRecord {
+    key: Vec<u8>,
+    provider: PeerId,
+    expires: Option<SystemTime>,
+    addresses: Vec<Multiaddr>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRecords {
+    schema_version: u32,
+    records: Vec<StoredRecord>,
+    providers: Vec<StoredProviderRecord>,
+}
+
+/// Kademlia record store that keeps everything in a `MemoryStore` (which enforces the limits)
+/// and optionally mirrors it to a JSON file so hosted records survive restarts.
+pub struct DissonanceRecordStore {
+    inner: MemoryStore,
+    local_id: PeerId,
+    path: Option<PathBuf>,
+    // `MemoryStore` cannot enumerate remote provider records, so track their keys here.
+    provider_keys: HashSet<RecordKey>,
+    dirty: bool,
+}
+
+impl DissonanceRecordStore {
+    pub fn new(local_id: PeerId, config: &RecordStoreConfig) -> Result<Self> {
+        let path = config.resolved_path()?;
+        let mut store = DissonanceRecordStore {
+            inner: MemoryStore::with_config(local_id, config.limits()),
+            local_id,
+            path: path.clone(),
+            provider_keys: HashSet::new(),
+            dirty: false,
+        };
+        if let Some(path) = &path
+            && path.exists() {
+            store.load_from_file(path)?;
+        }
+        Ok(store)
+    }
+
+    fn load_from_file(&mut self, path: &Path) -> Result<()> {
+        let content = fs::read_to_string(path).context("Failed to read record store")?;
+        let stored: StoredRecords = serde_json::from_str(&content).context("Failed to parse record store")?;
+        if stored.schema_version > RECORD_STORE_SCHEMA_VERSION {
+            bail!("Record store schema version {} is newer than supported version {}", stored.schema_version, RECORD_STORE_SCHEMA_VERSION);
+        }
+
+        let (now, system_now) = (Instant::now(), SystemTime::now());
+        let mut dropped = 0;
+        for stored_record in stored.records {
+            if stored_record.expires.is_some_and(|expires| expires <= system_now) {
+                dropped += 1;
+                continue;
+            }
+            let record = Record {
+                key: RecordKey::from(stored_record.key),
+                value: stored_record.value,
+                publisher: stored_record.publisher,
+                expires: stored_record.expires.map(|expires| to_instant(expires, now, system_now)),
+            };
+            if self.inner.put(record).is_err() {
+                dropped += 1;
+            }
+        }
+        for stored_provider in stored.providers {
+            if stored_provider.expires.is_some_and(|expires| expires <= system_now) {
+                dropped += 1;
+                continue;
+            }
+            let record = ProviderRecord {
+                key: RecordKey::from(stored_provider.key),
+                provider: stored_provider.provider,
+                expires: stored_provider.expires.map(|expires| to_instant(expires, now, system_now)),
+                addresses: stored_provider.addresses,
+            };
+            if self.add_provider(record).is_err() {
+                dropped += 1;
+            }
+        }
+        // Loading should not count as a change that needs writing back.
+        self.dirty = dropped > 0;
+        tracing::info!("[KAD] Loaded {} records from {} ({} expired or over limits)", self.inner.records().count(), path.display(), dropped);
+        Ok(())
+    }
+
+    /// Writes all unexpired records to disk if anything changed since the last flush.
+    pub fn flush(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let (now, system_now) = (Instant::now(), SystemTime::now());
+        let records = self.inner.records()
+            .filter(|record| !record.is_expired(now))
+            .map(|record| StoredRecord {
+                key: record.key.to_vec(),
+                value: record.value.clone(),
+                publisher: record.publisher,
+                expires: record.expires.map(|expires| to_system_time(expires, now, system_now)),
+            })
+            .collect();
+        let providers = self.provider_keys.iter()
+            .flat_map(|key| self.inner.providers(key))
+            .filter(|record| !record.is_expired(now))
+            .map(|record| StoredProviderRecord {
+                key: record.key.to_vec(),
+                provider: record.provider,
+                expires: record.expires.map(|expires| to_system_time(expires, now, system_now)),
+                addresses: record.addresses,
+            })
+            .collect();
+
+        let stored = StoredRecords { schema_version: RECORD_STORE_SCHEMA_VERSION, records, providers };
+        let content = serde_json::to_vec(&stored).context("Failed to serialize record store")?;
+        write_atomic(path, &content).context("Failed to write record store")?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Records this node originally published; these are re-announced on startup.
+    pub fn published_records(&self) -> Vec<Record> {
+        self.inner.records()
+            .filter(|record| record.publisher == Some(self.local_id))
+            .map(Cow::into_owned)
+            .collect()
+    }
+
+    /// Keys this node announced itself as a provider for.
+    pub fn provided_keys(&self) -> Vec<RecordKey> {
+        self.inner.provided().map(|record| record.key.clone()).collect()
+    }
+}
+
+fn to_system_time(expires: Instant, now: Instant, system_now: SystemTime) -> SystemTime {
+    system_now + expires.saturating_duration_since(now)
+}
+
+fn to_instant(expires: SystemTime, now: Instant, system_now: SystemTime) -> Instant {
+    now + expires.duration_since(system_now).unwrap_or_default()
+}
+
+impl RecordStore for DissonanceRecordStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.inner.get(k)
+    }
+
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        self.inner.put(r)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        self.inner.remove(k);
+        self.dirty = true;
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.inner.records()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        let key = record.key.clone();
+        self.inner.add_provider(record)?;
+        self.provider_keys.insert(key);
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.inner.providers(key)
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.inner.provided()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        self.inner.remove_provider(k, p);
+        if self.inner.providers(k).is_empty() {
+            self.provider_keys.remove(k);
+        }
+        self.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_records_survive_reload() {
+        let temp = tempdir().unwrap();
+        let config = RecordStoreConfig::disk(temp.path().join("kad-records.json"));
+        let local_id = PeerId::random();
+        let remote_provider = PeerId::random();
+
+        let mut store = DissonanceRecordStore::new(local_id, &config).unwrap();
+        let mut own = Record::new(RecordKey::new(&"own"), b"mine".to_vec());
+        own.publisher = Some(local_id);
+        own.expires = Some(Instant::now() + Duration::from_secs(3600));
+        store.put(own).unwrap();
+        store.put(Record::new(RecordKey::new(&"hosted"), b"theirs".to_vec())).unwrap();
+        let mut expired = Record::new(RecordKey::new(&"expired"), b"old".to_vec());
+        expired.expires = Some(Instant::now());
+        store.put(expired).unwrap();
+        store.add_provider(ProviderRecord::new(RecordKey::new(&"file"), remote_provider, vec![])).unwrap();
+        store.add_provider(ProviderRecord::new(RecordKey::new(&"mine"), local_id, vec![])).unwrap();
+        store.flush().unwrap();
+
+        let reloaded = DissonanceRecordStore::new(local_id, &config).unwrap();
+        assert_eq!(reloaded.records().count(), 2);
+        assert_eq!(reloaded.get(&RecordKey::new(&"hosted")).unwrap().value, b"theirs");
+        assert!(reloaded.get(&RecordKey::new(&"expired")).is_none());
+        assert_eq!(reloaded.providers(&RecordKey::new(&"file"))[0].provider, remote_provider);
+        assert_eq!(reloaded.provided_keys(), vec![RecordKey::new(&"mine")]);
+
+        let published = reloaded.published_records();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].value, b"mine");
+        assert!(published[0].expires.unwrap() > Instant::now() + Duration::from_secs(3500));
+    }
+
+    #[test]
+    fn test_limits_are_enforced() {
+        let mut config = RecordStoreConfig::memory();
+        config.max_records = 1;
+        config.max_value_bytes = 4;
+        let mut store = DissonanceRecordStore::new(PeerId::random(), &config).unwrap();
+
+        assert!(matches!(store.put(Record::new(RecordKey::new(&"big"), vec![0; 4])), Err(store::Error::ValueTooLarge)));
+        store.put(Record::new(RecordKey::new(&"a"), vec![0; 3])).unwrap();
+        assert!(matches!(store.put(Record::new(RecordKey::new(&"b"), vec![0; 3])), Err(store::Error::MaxRecords)));
+        store.flush().expect("Flushing a memory store is a no-op");
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::{bail, Result};
+use libp2p::{
+    multiaddr::Protocol,
+    relay::{Behaviour as RelayBehaviour, Config as RelayConfig},
+    swarm::behaviour::toggle::Toggle,
+    Multiaddr,
+};
+
+use crate::config::RelayServerOptions;
+use crate::NodeIdentity;
+
+/// The relay server role, off unless `relay.server.enabled` is set.
+pub fn get_relay_server(identity: &NodeIdentity, options: &RelayServerOptions) -> Toggle<RelayBehaviour> {
+    if !options.enabled {
+        return Toggle::from(None);
+    }
+    // Keeps the default per-peer and per-IP rate limiters.
+    let config = RelayConfig {
+        max_reservations: options.max_reservations,
+        max_reservations_per_peer: options.max_reservations_per_peer,
+        reservation_duration: options.reservation_duration,
+        max_circuits: options.max_circuits,
+        max_circuits_per_peer: options.max_circuits_per_peer,
+        max_circuit_duration: options.max_circuit_duration,
+        max_circuit_bytes: options.max_circuit_bytes,
+        ..RelayConfig::default()
+    };
+    Toggle::from(Some(RelayBehaviour::new(identity.peer_id(), config)))
+}
+
+/// The address to listen on to hold a reservation on `relay`.
+pub fn circuit_listen_addr(relay: &Multiaddr) -> Result<Multiaddr> {
+    if !matches!(relay.iter().last(), Some(Protocol::P2p(_))) {
+        bail!("Relay address {relay} must end with /p2p/<relay peer id>");
+    }
+    Ok(relay.clone().with(Protocol::P2pCircuit))
+}
+
+pub fn is_relayed(address: &Multiaddr) -> bool {
+    address.iter().any(|protocol| matches!(protocol, Protocol::P2pCircuit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_listen_addr_needs_relay_id() {
+        let relay: Multiaddr = "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".parse().unwrap();
+        let circuit = circuit_listen_addr(&relay).unwrap();
+        assert!(is_relayed(&circuit));
+        assert!(!is_relayed(&relay));
+        assert!(circuit_listen_addr(&"/ip4/203.0.113.7/tcp/4001".parse().unwrap()).is_err());
+    }
+}
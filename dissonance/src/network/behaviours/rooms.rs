@@ -0,0 +1,506 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use libp2p::{
+    core::{transport::PortUse, Endpoint},
+    gossipsub::{
+        self, Behaviour as GossipsubBehaviour, ConfigBuilder as GossipsubConfigBuilder, IdentTopic, MessageAcceptance,
+        MessageAuthenticity, PublishError, SubscriptionError, TopicHash, ValidationMode,
+    },
+    swarm::{ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm},
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::config::RoomsOptions;
+use crate::e2e::{peer_public_key, E2eError, E2eKeys, GroupEnvelope};
+use crate::network::behaviours::chat::MessageId;
+use crate::NodeIdentity;
+
+const ROOM_TOPIC_PREFIX: &str = "/dissonance/room/";
+pub const MAX_ROOM_MESSAGE_BODY: usize = 16 * 1024;
+// Room, id, sender and timestamp are serialized alongside the body before sealing.
+const MAX_ROOM_PAYLOAD: usize = MAX_ROOM_MESSAGE_BODY + 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomMessage {
+    pub id: MessageId,
+    pub sender: PeerId,
+    pub room: String,
+    pub timestamp: SystemTime,
+    pub body: String,
+}
+
+impl RoomMessage {
+    pub fn new(sender: PeerId, room: impl Into<String>, body: impl Into<String>) -> Self {
+        RoomMessage { id: MessageId::random(), sender, room: room.into(), timestamp: SystemTime::now(), body: body.into() }
+    }
+
+    pub fn seal(&self, keys: &E2eKeys, members: &[PeerId]) -> Result<SealedRoomMessage, E2eError> {
+        let plaintext = serde_json::to_vec(self).expect("room messages always serialize");
+        Ok(SealedRoomMessage {
+            id: self.id.clone(),
+            sender: self.sender,
+            room: self.room.clone(),
+            envelope: keys.seal_group(members, &plaintext)?,
+        })
+    }
+}
+
+/// Gossip payload: routing fields stay in the clear so every hop can validate them,
+/// the body is only readable by the members the sender knew of when publishing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedRoomMessage {
+    pub id: MessageId,
+    pub sender: PeerId,
+    pub room: String,
+    pub envelope: GroupEnvelope,
+}
+
+/// A member telling the whole room, not only its direct neighbours, that it is there and which
+/// key to seal for. Repeated every `RoomsOptions::announce_interval`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberAnnouncement {
+    pub member: PeerId,
+    pub room: String,
+    /// The member's X25519 key, which must be the one derived from its peer id.
+    pub public_key: [u8; 32],
+    /// Sent once when the member leaves the room.
+    pub leaving: bool,
+}
+
+/// Everything published on a room topic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomPayload {
+    Message(SealedRoomMessage),
+    Member(MemberAnnouncement),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomValidationError {
+    Malformed(String),
+    MissingAuthor,
+    SenderMismatch { claimed: PeerId },
+    RoomMismatch { claimed: String, topic: String },
+    /// An announcement carried a key other than the one the member's peer id derives.
+    WrongMemberKey { member: PeerId },
+    EmptyBody,
+    BodyTooLarge(usize),
+    Undecryptable(E2eError),
+    Tampered,
+}
+
+impl fmt::Display for RoomValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomValidationError::Malformed(e) => write!(f, "malformed payload: {e}"),
+            RoomValidationError::MissingAuthor => write!(f, "message is not signed by an author"),
+            RoomValidationError::SenderMismatch { claimed } => write!(f, "claimed sender {claimed} is not the signing author"),
+            RoomValidationError::RoomMismatch { claimed, topic } => write!(f, "room {claimed} does not match topic {topic}"),
+            RoomValidationError::WrongMemberKey { member } => write!(f, "announced key does not belong to {member}"),
+            RoomValidationError::EmptyBody => write!(f, "empty message body"),
+            RoomValidationError::BodyTooLarge(len) => write!(f, "message body of {len} bytes exceeds {MAX_ROOM_MESSAGE_BODY}"),
+            RoomValidationError::Undecryptable(e) => write!(f, "could not open envelope: {e}"),
+            RoomValidationError::Tampered => write!(f, "sealed contents do not match the message header"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RoomEvent {
+    /// The local node subscribed to a room.
+    Joined { room: String },
+    /// The local node unsubscribed from a room.
+    Left { room: String },
+    /// A member announced itself in a room for the first time, directly or through other members.
+    MemberJoined { room: String, peer: PeerId },
+    /// A member announced it left, or stopped announcing itself.
+    MemberLeft { room: String, peer: PeerId },
+    Message { propagation_source: PeerId, message: RoomMessage },
+    /// A message failed the checks every hop can make and was rejected, which also penalises the
+    /// forwarding peer in gossipsub's scoring. `source` is the signing author, if there was one.
+    Rejected { room: String, source: Option<PeerId>, propagation_source: PeerId, error: RoomValidationError },
+    /// A valid message whose sealed contents were bad. Forwarders cannot see inside the envelope, so
+    /// the message is still accepted and only `sender` is to blame.
+    Forged { room: String, sender: PeerId, id: MessageId, error: RoomValidationError },
+    /// A valid message that was sealed for other members, e.g. sent before we joined.
+    Unreadable { room: String, sender: PeerId, id: MessageId },
+    Unsupported { peer: PeerId },
+}
+
+pub fn room_topic(room: &str) -> IdentTopic {
+    IdentTopic::new(format!("{ROOM_TOPIC_PREFIX}{room}"))
+}
+
+pub fn room_name(topic: &TopicHash) -> Option<&str> {
+    topic.as_str().strip_prefix(ROOM_TOPIC_PREFIX)
+}
+
+pub fn validate_room_payload(message: &gossipsub::Message) -> Result<RoomPayload, RoomValidationError> {
+    let author = message.source.ok_or(RoomValidationError::MissingAuthor)?;
+    let payload: RoomPayload = serde_json::from_slice(&message.data)
+        .map_err(|e| RoomValidationError::Malformed(e.to_string()))?;
+
+    let (sender, room) = match &payload {
+        RoomPayload::Message(sealed) => {
+            if sealed.envelope.sender != author {
+                return Err(RoomValidationError::SenderMismatch { claimed: sealed.envelope.sender });
+            }
+            if sealed.envelope.ciphertext_len() > MAX_ROOM_PAYLOAD {
+                return Err(RoomValidationError::BodyTooLarge(sealed.envelope.ciphertext_len()));
+            }
+            (sealed.sender, &sealed.room)
+        }
+        RoomPayload::Member(announcement) => {
+            if peer_public_key(&announcement.member).map(|key| key.to_bytes()) != Ok(announcement.public_key) {
+                return Err(RoomValidationError::WrongMemberKey { member: announcement.member });
+            }
+            (announcement.member, &announcement.room)
+        }
+    };
+    if sender != author {
+        return Err(RoomValidationError::SenderMismatch { claimed: sender });
+    }
+    if room_name(&message.topic) != Some(room.as_str()) {
+        return Err(RoomValidationError::RoomMismatch { claimed: room.clone(), topic: message.topic.to_string() });
+    }
+    Ok(payload)
+}
+
+/// Opens a validated message, returning `None` when it was not sealed for this node.
+pub fn open_room_message(keys: &E2eKeys, sealed: &SealedRoomMessage) -> Result<Option<RoomMessage>, RoomValidationError> {
+    let plaintext = match keys.open_group(&sealed.envelope) {
+        Ok(plaintext) => plaintext,
+        Err(E2eError::NotARecipient) => return Ok(None),
+        Err(e) => return Err(RoomValidationError::Undecryptable(e)),
+    };
+    let room_message: RoomMessage = serde_json::from_slice(&plaintext)
+        .map_err(|e| RoomValidationError::Malformed(e.to_string()))?;
+
+    if room_message.id != sealed.id || room_message.sender != sealed.sender || room_message.room != sealed.room {
+        return Err(RoomValidationError::Tampered);
+    }
+    if room_message.body.trim().is_empty() {
+        return Err(RoomValidationError::EmptyBody);
+    }
+    if room_message.body.len() > MAX_ROOM_MESSAGE_BODY {
+        return Err(RoomValidationError::BodyTooLarge(room_message.body.len()));
+    }
+    Ok(Some(room_message))
+}
+
+/// Gossipsub wrapper that maps topics to named rooms and validates every message before it is forwarded.
+/// Members announce themselves on the room topic, so messages are sealed for everyone in the room
+/// rather than only the peers we happen to be connected to.
+pub struct RoomsBehaviour {
+    gossipsub: GossipsubBehaviour,
+    keys: E2eKeys,
+    /// Everyone known to be in each joined room, with when they last announced themselves.
+    members: HashMap<String, HashMap<PeerId, Instant>>,
+    /// Rooms to announce ourselves in on the next poll.
+    pending_announcements: HashSet<String>,
+    announce_timer: Interval,
+    member_ttl: Duration,
+    events: VecDeque<RoomEvent>,
+}
+
+impl RoomsBehaviour {
+    pub fn join(&mut self, room: &str) -> Result<bool, SubscriptionError> {
+        let joined = self.gossipsub.subscribe(&room_topic(room))?;
+        if joined {
+            self.members.entry(room.to_string()).or_default();
+            self.pending_announcements.insert(room.to_string());
+            self.events.push_back(RoomEvent::Joined { room: room.to_string() });
+        }
+        Ok(joined)
+    }
+
+    pub fn leave(&mut self, room: &str) -> bool {
+        if self.members.contains_key(room) {
+            // Best effort; members that miss it drop us once our announcements stop.
+            let _ = self.announce(room, true);
+        }
+        let left = self.gossipsub.unsubscribe(&room_topic(room));
+        if left {
+            self.events.push_back(RoomEvent::Left { room: room.to_string() });
+        }
+        self.members.remove(room);
+        self.pending_announcements.remove(room);
+        left
+    }
+
+    pub fn publish(&mut self, message: &RoomMessage) -> Result<gossipsub::MessageId, PublishError> {
+        let members = self.members(&message.room);
+        if members.is_empty() {
+            return Err(PublishError::NoPeersSubscribedToTopic);
+        }
+        let sealed = message.seal(&self.keys, &members).map_err(|e| PublishError::TransformFailed(std::io::Error::other(e)))?;
+        let payload = serde_json::to_vec(&RoomPayload::Message(sealed)).map_err(|e| PublishError::TransformFailed(std::io::Error::other(e)))?;
+        self.gossipsub.publish(room_topic(&message.room), payload)
+    }
+
+    pub fn rooms(&self) -> Vec<String> {
+        self.gossipsub.topics().filter_map(room_name).map(str::to_string).collect()
+    }
+
+    /// Members that announced themselves in `room`, wherever they are in the mesh.
+    pub fn members(&self, room: &str) -> Vec<PeerId> {
+        self.members.get(room).map(|members| members.keys().copied().collect()).unwrap_or_default()
+    }
+
+    fn announce(&mut self, room: &str, leaving: bool) -> Result<gossipsub::MessageId, PublishError> {
+        let announcement = MemberAnnouncement {
+            member: self.keys.peer_id(),
+            room: room.to_string(),
+            public_key: self.keys.public_key().to_bytes(),
+            leaving,
+        };
+        let payload = serde_json::to_vec(&RoomPayload::Member(announcement)).map_err(|e| PublishError::TransformFailed(std::io::Error::other(e)))?;
+        self.gossipsub.publish(room_topic(room), payload)
+    }
+
+    /// Drops members that have not announced themselves for `member_ttl`.
+    fn expire_members(&mut self) {
+        let now = Instant::now();
+        for (room, members) in &mut self.members {
+            members.retain(|peer, last_seen| {
+                let alive = now.duration_since(*last_seen) < self.member_ttl;
+                if !alive {
+                    self.events.push_back(RoomEvent::MemberLeft { room: room.clone(), peer: *peer });
+                }
+                alive
+            });
+        }
+    }
+
+    fn on_announcement(&mut self, announcement: MemberAnnouncement) -> Option<RoomEvent> {
+        let MemberAnnouncement { member, room, leaving, .. } = announcement;
+        let members = self.members.get_mut(&room)?;
+        if leaving {
+            return members.remove(&member).map(|_| RoomEvent::MemberLeft { room, peer: member });
+        }
+        if members.insert(member, Instant::now()).is_some() {
+            return None;
+        }
+        // The newcomer may not have heard of us yet.
+        self.pending_announcements.insert(room.clone());
+        Some(RoomEvent::MemberJoined { room, peer: member })
+    }
+
+    fn on_gossipsub_event(&mut self, event: gossipsub::Event) -> Option<RoomEvent> {
+        match event {
+            gossipsub::Event::Message { propagation_source, message_id, message } => {
+                let payload = match validate_room_payload(&message) {
+                    Ok(payload) => payload,
+                    Err(error) => {
+                        self.gossipsub.report_message_validation_result(&message_id, &propagation_source, MessageAcceptance::Reject);
+                        let room = room_name(&message.topic).unwrap_or(message.topic.as_str()).to_string();
+                        return Some(RoomEvent::Rejected { room, source: message.source, propagation_source, error });
+                    }
+                };
+                // Non-recipients accept the same message, so accept it here too.
+                self.gossipsub.report_message_validation_result(&message_id, &propagation_source, MessageAcceptance::Accept);
+                let sealed = match payload {
+                    RoomPayload::Message(sealed) => sealed,
+                    RoomPayload::Member(announcement) => return self.on_announcement(announcement),
+                };
+                match open_room_message(&self.keys, &sealed) {
+                    Ok(Some(room_message)) => Some(RoomEvent::Message { propagation_source, message: room_message }),
+                    Ok(None) => Some(RoomEvent::Unreadable { room: sealed.room, sender: sealed.sender, id: sealed.id }),
+                    Err(error) => Some(RoomEvent::Forged { room: sealed.room, sender: sealed.sender, id: sealed.id, error }),
+                }
+            }
+            gossipsub::Event::Subscribed { topic, .. } => {
+                // A neighbour joined; tell it, and through it the rest of the room, that we are here.
+                // It becomes a member once its own announcement arrives.
+                if let Some(room) = room_name(&topic).filter(|room| self.members.contains_key(*room)) {
+                    self.pending_announcements.insert(room.to_string());
+                }
+                None
+            }
+            gossipsub::Event::Unsubscribed { .. } => None,
+            gossipsub::Event::GossipsubNotSupported { peer_id } => Some(RoomEvent::Unsupported { peer: peer_id }),
+            gossipsub::Event::SlowPeer { .. } => None,
+        }
+    }
+}
+
+impl NetworkBehaviour for RoomsBehaviour {
+    type ConnectionHandler = <GossipsubBehaviour as NetworkBehaviour>::ConnectionHandler;
+    type ToSwarm = RoomEvent;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.gossipsub.handle_established_inbound_connection(connection_id, peer, local_addr, remote_addr)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+        port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.gossipsub.handle_established_outbound_connection(connection_id, peer, addr, role_override, port_use)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.gossipsub.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(&mut self, peer_id: PeerId, connection_id: ConnectionId, event: THandlerOutEvent<Self>) {
+        self.gossipsub.on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        while self.announce_timer.poll_tick(cx).is_ready() {
+            self.expire_members();
+            self.pending_announcements.extend(self.members.keys().cloned());
+        }
+        loop {
+            for room in std::mem::take(&mut self.pending_announcements) {
+                // Fails while no other member is reachable; the next tick tries again.
+                let _ = self.announce(&room, false);
+            }
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(ToSwarm::GenerateEvent(event));
+            }
+            match self.gossipsub.poll(cx) {
+                Poll::Ready(ToSwarm::GenerateEvent(event)) => {
+                    if let Some(event) = self.on_gossipsub_event(event) {
+                        return Poll::Ready(ToSwarm::GenerateEvent(event));
+                    }
+                }
+                Poll::Ready(other) => return Poll::Ready(other.map_out(|_| unreachable!("GenerateEvent handled above"))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+pub fn get_rooms(identity: &NodeIdentity, options: &RoomsOptions) -> RoomsBehaviour {
+    let keypair = identity.to_lp2p_keypair().unwrap();
+    let gossipsub_config = GossipsubConfigBuilder::default()
+        .heartbeat_interval(options.heartbeat_interval)
+        .validation_mode(ValidationMode::Strict)
+        .validate_messages()
+        .build()
+        .unwrap();
+
+    let gossipsub = GossipsubBehaviour::new(MessageAuthenticity::Signed(keypair), gossipsub_config).unwrap();
+    let mut announce_timer = tokio::time::interval(options.announce_interval);
+    announce_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    RoomsBehaviour {
+        gossipsub,
+        keys: E2eKeys::from_identity(identity),
+        members: HashMap::new(),
+        pending_announcements: HashSet::new(),
+        announce_timer,
+        member_ttl: options.announce_interval * 3,
+        events: VecDeque::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> E2eKeys {
+        E2eKeys::from_identity(&NodeIdentity::generate_ephemeral().unwrap())
+    }
+
+    fn gossip_message(source: Option<PeerId>, room: &str, payload: RoomPayload) -> gossipsub::Message {
+        gossipsub::Message {
+            source,
+            data: serde_json::to_vec(&payload).unwrap(),
+            sequence_number: Some(1),
+            topic: room_topic(room).hash(),
+        }
+    }
+
+    #[test]
+    fn test_room_topic_roundtrip() {
+        let topic = room_topic("general").hash();
+        assert_eq!(room_name(&topic), Some("general"));
+        assert_eq!(room_name(&IdentTopic::new("other").hash()), None);
+    }
+
+    #[test]
+    fn test_validate_and_open_signed_message() {
+        let (alice, bob, eve) = (keys(), keys(), keys());
+        let message = RoomMessage::new(alice.peer_id(), "general", "hello room");
+        let sealed = message.seal(&alice, &[bob.peer_id()]).unwrap();
+
+        let validated = match validate_room_payload(&gossip_message(Some(alice.peer_id()), "general", RoomPayload::Message(sealed))) {
+            Ok(RoomPayload::Message(sealed)) => sealed,
+            other => panic!("expected a message, got {other:?}"),
+        };
+        let opened = open_room_message(&bob, &validated).unwrap().expect("bob is a recipient");
+        assert_eq!(opened.id, message.id);
+        assert_eq!(opened.body, "hello room");
+        assert!(open_room_message(&eve, &validated).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_validate_rejects_spoofed_and_invalid_messages() {
+        let (alice, bob) = (keys(), keys());
+        let spoofed = RoomMessage::new(bob.peer_id(), "general", "hi").seal(&alice, &[bob.peer_id()]).unwrap();
+        assert!(matches!(
+            validate_room_payload(&gossip_message(Some(alice.peer_id()), "general", RoomPayload::Message(spoofed))),
+            Err(RoomValidationError::SenderMismatch { .. })
+        ));
+
+        let sealed = RoomMessage::new(alice.peer_id(), "general", "hi").seal(&alice, &[bob.peer_id()]).unwrap();
+        assert_eq!(validate_room_payload(&gossip_message(None, "general", RoomPayload::Message(sealed.clone()))).unwrap_err(), RoomValidationError::MissingAuthor);
+        assert!(matches!(
+            validate_room_payload(&gossip_message(Some(alice.peer_id()), "random", RoomPayload::Message(sealed.clone()))),
+            Err(RoomValidationError::RoomMismatch { .. })
+        ));
+
+        let empty = RoomMessage::new(alice.peer_id(), "general", "   ").seal(&alice, &[bob.peer_id()]).unwrap();
+        assert_eq!(open_room_message(&bob, &empty).unwrap_err(), RoomValidationError::EmptyBody);
+
+        let mut relabelled = sealed;
+        relabelled.room = "random".to_string();
+        assert_eq!(open_room_message(&bob, &relabelled).unwrap_err(), RoomValidationError::Tampered);
+    }
+
+    #[test]
+    fn test_validate_member_announcements() {
+        let (alice, bob) = (keys(), keys());
+        let announcement = |member: &E2eKeys, public_key: [u8; 32]| RoomPayload::Member(MemberAnnouncement {
+            member: member.peer_id(),
+            room: "general".to_string(),
+            public_key,
+            leaving: false,
+        });
+
+        let honest = announcement(&alice, alice.public_key().to_bytes());
+        assert!(matches!(
+            validate_room_payload(&gossip_message(Some(alice.peer_id()), "general", honest.clone())),
+            Ok(RoomPayload::Member(MemberAnnouncement { member, .. })) if member == alice.peer_id()
+        ));
+        assert!(matches!(
+            validate_room_payload(&gossip_message(Some(bob.peer_id()), "general", honest)),
+            Err(RoomValidationError::SenderMismatch { .. })
+        ));
+
+        let wrong_key = announcement(&alice, bob.public_key().to_bytes());
+        assert_eq!(
+            validate_room_payload(&gossip_message(Some(alice.peer_id()), "general", wrong_key)).unwrap_err(),
+            RoomValidationError::WrongMemberKey { member: alice.peer_id() }
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fs, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey, PUBLIC_KEY_LENGTH};
+use libp2p::{identity, kad::RecordKey, request_response::{self, json, ProtocolSupport}, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+
+use crate::store::write_atomic;
+use crate::NodeIdentity;
+
+pub const SUCCESSION_PROTOCOL: StreamProtocol = StreamProtocol::new("/dissonance/succession/1.0.0");
+
+pub const SUCCESSION_FILE: &str = "successions.json";
+
+const SUCCESSION_CONTEXT: &[u8] = b"dissonance-succession-v1";
+
+pub type SuccessionBehaviour = json::Behaviour<SuccessionCertificate, SuccessionAck>;
+pub type SuccessionEvent = request_response::Event<SuccessionCertificate, SuccessionAck>;
+
+/// Statement signed by a retired identity key naming the key that replaces it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SuccessionCertificate {
+    pub previous_key: [u8; PUBLIC_KEY_LENGTH],
+    pub successor_key: [u8; PUBLIC_KEY_LENGTH],
+    /// Seconds since the Unix epoch.
+    pub issued_at: u64,
+    pub signature: Vec<u8>,
+}
+
+impl SuccessionCertificate {
+    /// Signs over to `successor` with the key of `previous`.
+    pub fn issue(previous: &NodeIdentity, successor: &NodeIdentity) -> Self {
+        let issued_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let previous_key = previous.pub_key_bytes();
+        let successor_key = successor.pub_key_bytes();
+        let signature = previous.signing_key.sign(&signed_bytes(&previous_key, &successor_key, issued_at));
+        SuccessionCertificate { previous_key, successor_key, issued_at, signature: signature.to_bytes().to_vec() }
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        if self.previous_key == self.successor_key {
+            bail!("Certificate names the same key twice");
+        }
+        let previous = VerifyingKey::from_bytes(&self.previous_key).context("Invalid previous key")?;
+        let signature = Signature::from_slice(&self.signature).context("Malformed signature")?;
+        previous
+            .verify(&signed_bytes(&self.previous_key, &self.successor_key, self.issued_at), &signature)
+            .context("Succession signature does not match the previous key")
+    }
+
+    pub fn previous(&self) -> Result<PeerId> {
+        peer_id_from_key(&self.previous_key)
+    }
+
+    pub fn successor(&self) -> Result<PeerId> {
+        peer_id_from_key(&self.successor_key)
+    }
+
+    pub fn issued_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.issued_at)
+    }
+
+    /// DHT key the certificate for `previous` is published under.
+    pub fn record_key(previous: &PeerId) -> RecordKey {
+        RecordKey::new(&format!("/dissonance/succession/{previous}"))
+    }
+
+    pub fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(SUCCESSION_FILE)
+    }
+
+    /// Every certificate this node has issued, oldest first.
+    pub fn load_chain(path: &Path) -> Result<Vec<Self>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(path).context("Failed to read succession certificates")?;
+        serde_json::from_str(&content).context("Failed to parse succession certificates")
+    }
+
+    /// Appends the certificate to the chain stored at `path`.
+    pub fn append_to(&self, path: &Path) -> Result<()> {
+        let mut chain = Self::load_chain(path)?;
+        chain.push(self.clone());
+        let content = serde_json::to_vec_pretty(&chain).context("Failed to serialize succession certificates")?;
+        write_atomic(path, &content)
+    }
+}
+
+fn signed_bytes(previous_key: &[u8], successor_key: &[u8], issued_at: u64) -> Vec<u8> {
+    let mut bytes = SUCCESSION_CONTEXT.to_vec();
+    bytes.extend_from_slice(previous_key);
+    bytes.extend_from_slice(successor_key);
+    bytes.extend_from_slice(&issued_at.to_be_bytes());
+    bytes
+}
+
+fn peer_id_from_key(key: &[u8; PUBLIC_KEY_LENGTH]) -> Result<PeerId> {
+    let public_key = identity::ed25519::PublicKey::try_from_bytes(key).context("Invalid ed25519 key")?;
+    Ok(PeerId::from_public_key(&identity::PublicKey::from(public_key)))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SuccessionAck {
+    pub accepted: bool,
+}
+
+pub fn get_succession() -> SuccessionBehaviour {
+    SuccessionBehaviour::new([(SUCCESSION_PROTOCOL, ProtocolSupport::Full)], request_response::Config::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_certificate_verifies_and_names_both_peers() {
+        let previous = NodeIdentity::generate_ephemeral().unwrap();
+        let successor = NodeIdentity::generate_ephemeral().unwrap();
+        let certificate = SuccessionCertificate::issue(&previous, &successor);
+
+        certificate.verify().expect("Certificate should verify");
+        assert_eq!(certificate.previous().unwrap(), previous.peer_id());
+        assert_eq!(certificate.successor().unwrap(), successor.peer_id());
+
+        let mut redirected = certificate.clone();
+        redirected.successor_key = NodeIdentity::generate_ephemeral().unwrap().pub_key_bytes();
+        assert!(redirected.verify().is_err(), "Changing the successor must break the signature");
+
+        // Only the previous key can sign the statement.
+        let mallory = NodeIdentity::generate_ephemeral().unwrap();
+        let mut forged = SuccessionCertificate::issue(&mallory, &successor);
+        forged.previous_key = previous.pub_key_bytes();
+        assert!(forged.verify().is_err());
+    }
+
+    #[test]
+    fn test_chain_roundtrip() {
+        let temp = tempdir().unwrap();
+        let path = SuccessionCertificate::path(temp.path());
+        assert!(SuccessionCertificate::load_chain(&path).unwrap().is_empty());
+
+        let identities: Vec<_> = (0..3).map(|_| NodeIdentity::generate_ephemeral().unwrap()).collect();
+        for pair in identities.windows(2) {
+            SuccessionCertificate::issue(&pair[0], &pair[1]).append_to(&path).unwrap();
+        }
+        let chain = SuccessionCertificate::load_chain(&path).unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[1].successor().unwrap(), identities[2].peer_id());
+    }
+}
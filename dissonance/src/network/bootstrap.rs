@@ -0,0 +1,203 @@
+use std::time::Duration;
+
+use libp2p::{
+    kad::{BootstrapError, BootstrapOk, ProgressStep, QueryId},
+    multiaddr::Protocol,
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::config::duration_secs;
+use crate::network::behaviour::DissonanceBehaviour;
+use crate::store::PeerStore;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BootstrapConfig {
+    /// `/ip4/.../p2p/<peer>` addresses of well-known nodes.
+    pub peers: Vec<Multiaddr>,
+    /// How often to re-run a successful bootstrap to keep the routing table fresh.
+    #[serde(with = "duration_secs")]
+    pub interval: Duration,
+    #[serde(with = "duration_secs")]
+    pub initial_backoff: Duration,
+    #[serde(with = "duration_secs")]
+    pub max_backoff: Duration,
+}
+
+impl Default for BootstrapConfig {
+    fn default() -> Self {
+        BootstrapConfig {
+            peers: vec![],
+            interval: Duration::from_secs(5 * 60),
+            initial_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BootstrapEvent {
+    Started { query_id: QueryId },
+    Succeeded { query_id: QueryId, next_in: Duration },
+    Failed { reason: String, failures: u32, retry_in: Duration },
+}
+
+/// Drives Kademlia bootstrapping: seeds the routing table, runs `bootstrap()` on a schedule and
+/// backs off exponentially while it keeps failing.
+#[derive(Debug)]
+pub struct Bootstrapper {
+    config: BootstrapConfig,
+    in_flight: Option<QueryId>,
+    next_attempt: Instant,
+    failures: u32,
+}
+
+impl Bootstrapper {
+    pub fn new(config: BootstrapConfig) -> Self {
+        Bootstrapper { config, in_flight: None, next_attempt: Instant::now(), failures: 0 }
+    }
+
+    /// Splits a bootstrap multiaddr into the peer id and the dialable address.
+    pub fn parse_peer(address: &Multiaddr) -> Option<(PeerId, Multiaddr)> {
+        let mut address = address.clone();
+        match address.pop() {
+            Some(Protocol::P2p(peer_id)) => Some((peer_id, address)),
+            _ => None,
+        }
+    }
+
+    /// Adds configured bootstrap peers and every remembered peer address to Kademlia.
+    /// Returns the number of addresses added.
+    pub fn seed(&self, behaviour: &mut DissonanceBehaviour, peer_store: &mut PeerStore) -> usize {
+        let mut added = 0;
+        for address in &self.config.peers {
+            match Self::parse_peer(address) {
+                Some((peer_id, address)) => {
+                    behaviour.add_kademlia_address(&peer_id, address);
+                    added += 1;
+                }
+                None => tracing::warn!("[BOOTSTRAP] Ignoring bootstrap address without /p2p/<peer>: {}", address),
+            }
+        }
+        for (peer_id, info) in peer_store.list_peers() {
+            for address in &info.addresses {
+                behaviour.add_kademlia_address(peer_id, address.clone());
+                added += 1;
+            }
+        }
+        added
+    }
+
+    pub fn next_attempt(&self) -> Instant {
+        self.next_attempt
+    }
+
+    /// Starts a bootstrap query if one is due. Call this when `next_attempt` elapses.
+    pub fn poll(&mut self, behaviour: &mut DissonanceBehaviour) -> Option<BootstrapEvent> {
+        if self.in_flight.is_some() || Instant::now() < self.next_attempt {
+            return None;
+        }
+        match behaviour.bootstrap_kad() {
+            Ok(query_id) => {
+                self.in_flight = Some(query_id);
+                // Guard against a query that never reports back.
+                self.next_attempt = Instant::now() + self.config.max_backoff;
+                Some(BootstrapEvent::Started { query_id })
+            }
+            Err(e) => Some(self.fail(e.to_string())),
+        }
+    }
+
+    /// Feeds a `QueryResult::Bootstrap` progress update back into the scheduler.
+    pub fn on_query_progressed(&mut self, query_id: QueryId, result: &Result<BootstrapOk, BootstrapError>, step: &ProgressStep) -> Option<BootstrapEvent> {
+        if self.in_flight != Some(query_id) {
+            return None;
+        }
+        match result {
+            Err(e) => {
+                self.in_flight = None;
+                Some(self.fail(e.to_string()))
+            }
+            Ok(_) if step.last => {
+                self.in_flight = None;
+                self.failures = 0;
+                self.next_attempt = Instant::now() + self.config.interval;
+                Some(BootstrapEvent::Succeeded { query_id, next_in: self.config.interval })
+            }
+            Ok(_) => None,
+        }
+    }
+
+    fn fail(&mut self, reason: String) -> BootstrapEvent {
+        self.failures += 1;
+        let retry_in = self.backoff();
+        self.next_attempt = Instant::now() + retry_in;
+        BootstrapEvent::Failed { reason, failures: self.failures, retry_in }
+    }
+
+    fn backoff(&self) -> Duration {
+        let exponent = self.failures.saturating_sub(1).min(16);
+        self.config.initial_backoff.saturating_mul(1 << exponent).min(self.config.max_backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::NodeIdentity;
+
+    #[test]
+    fn test_parse_peer() {
+        let peer_id = PeerId::random();
+        let address: Multiaddr = format!("/ip4/10.0.0.1/tcp/4001/p2p/{peer_id}").parse().unwrap();
+        let (parsed_id, dial_address) = Bootstrapper::parse_peer(&address).unwrap();
+        assert_eq!(parsed_id, peer_id);
+        assert_eq!(dial_address, "/ip4/10.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap());
+
+        assert!(Bootstrapper::parse_peer(&"/ip4/10.0.0.1/tcp/4001".parse().unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_backoff_grows_until_capped() {
+        let identity = NodeIdentity::generate_ephemeral().unwrap();
+        let mut behaviour = DissonanceBehaviour::new(&identity, &Config::ephemeral(), libp2p::relay::client::new(identity.peer_id()).1).unwrap();
+        let mut bootstrapper = Bootstrapper::new(BootstrapConfig {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+            ..Default::default()
+        });
+
+        // With an empty routing table every attempt fails with `NoKnownPeers`.
+        let mut delays = vec![];
+        for _ in 0..4 {
+            bootstrapper.next_attempt = Instant::now();
+            match bootstrapper.poll(&mut behaviour) {
+                Some(BootstrapEvent::Failed { retry_in, .. }) => delays.push(retry_in.as_secs()),
+                other => panic!("Expected failure, got {other:?}"),
+            }
+        }
+        assert_eq!(delays, vec![1, 2, 3, 3]);
+        assert!(bootstrapper.poll(&mut behaviour).is_none(), "Should wait for the backoff to elapse");
+    }
+
+    #[tokio::test]
+    async fn test_seed_adds_configured_and_stored_peers() {
+        let identity = NodeIdentity::generate_ephemeral().unwrap();
+        let mut behaviour = DissonanceBehaviour::new(&identity, &Config::ephemeral(), libp2p::relay::client::new(identity.peer_id()).1).unwrap();
+        let mut peer_store = PeerStore::new();
+        peer_store.add_peer_address(&PeerId::random(), "/ip4/10.0.0.2/tcp/4001".parse().unwrap());
+
+        let mut bootstrapper = Bootstrapper::new(BootstrapConfig {
+            peers: vec![
+                format!("/ip4/10.0.0.1/tcp/4001/p2p/{}", PeerId::random()).parse().unwrap(),
+                "/ip4/10.0.0.3/tcp/4001".parse().unwrap(),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(bootstrapper.seed(&mut behaviour, &mut peer_store), 2);
+        assert!(matches!(bootstrapper.poll(&mut behaviour), Some(BootstrapEvent::Started { .. })));
+    }
+}
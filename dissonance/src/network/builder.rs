@@ -1,28 +1,57 @@
-use libp2p::swarm:: Swarm;
+use anyhow::Context;
+use libp2p::{
+    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade::Version},
+    dns, identity, quic, swarm::Swarm, tcp, PeerId, Transport,
+};
 use crate::network::transport::{
     noise::build_noise_config,
+    quic::build_quic_config,
     tcp::build_tcp_config,
+    websocket::build_websocket_transport,
     yamux::build_yamux_config
 };
 
 use super::NodeIdentity;
 use super::behaviour::{DissonanceBehaviour,};
+use crate::config::Config;
 
-pub fn build_swarm(identity: &NodeIdentity) -> anyhow::Result<Swarm<DissonanceBehaviour>>{
+pub fn build_swarm(identity: &NodeIdentity, config: &Config) -> anyhow::Result<Swarm<DissonanceBehaviour>>{
 
     let lp2p_keypair = identity.to_lp2p_keypair()?;    
-    let dissonance_behaviour = DissonanceBehaviour::new(identity);
+    let transport = build_transport(&lp2p_keypair, config)?;
     let swarm = libp2p::SwarmBuilder::with_existing_identity(lp2p_keypair)
     .with_tokio()
-    .with_tcp(build_tcp_config(), build_noise_config, build_yamux_config,)?
-    .with_behaviour(|_key| {
-        Ok(dissonance_behaviour)
+    .with_other_transport(|_key| transport)?
+    .with_relay_client(build_noise_config, || build_yamux_config(&config.yamux))?
+    .with_behaviour(|_key, relay_client| {
+        DissonanceBehaviour::new(identity, config, relay_client).map_err(Into::into)
          })?
         .build();
 
     Ok(swarm)
 }
 
+/// TCP and QUIC behind a DNS resolver, next to WebSocket. The WebSocket transport resolves
+/// hostnames itself: wrapping it in the same resolver would hand it `/ip4` addresses and TLS
+/// would check the certificate against the IP instead of the name.
+fn build_transport(keypair: &identity::Keypair, config: &Config) -> anyhow::Result<Boxed<(PeerId, StreamMuxerBox)>>{
+    let tcp = tcp::tokio::Transport::new(build_tcp_config())
+        .upgrade(Version::V1Lazy)
+        .authenticate(build_noise_config(keypair)?)
+        .multiplex(build_yamux_config(&config.yamux));
+    let quic = quic::tokio::Transport::new(build_quic_config(quic::Config::new(keypair), &config.quic));
+    let direct = tcp
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+        .or_transport(quic.map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer))))
+        .map(|either, _| either.into_inner());
+    let direct = dns::tokio::Transport::system(direct).context("Failed to read the system DNS configuration")?;
+    let websocket = build_websocket_transport(keypair, &config.yamux, &config.websocket)?;
+    Ok(websocket
+        .or_transport(direct)
+        .map(|either, _| either.into_inner())
+        .boxed())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -36,8 +65,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_build_swarm_success() {
-        let identity = NodeIdentity::get_identity().expect("Could not generate identity");
-        let swarm_result = build_swarm(&identity);
+        let identity = NodeIdentity::generate_ephemeral().expect("Could not generate identity");
+        let swarm_result = build_swarm(&identity, &Config::ephemeral());
         assert!(swarm_result.is_ok(), "Failed to build swarm");
 
         let swarm = swarm_result.unwrap();
@@ -47,11 +76,123 @@ mod tests {
 
     #[tokio::test]
     async fn test_swarm_has_dissonance_behaviour() {
-        let identity = NodeIdentity::get_identity().unwrap();
-        let swarm = build_swarm(&identity).unwrap();
+        let identity = NodeIdentity::generate_ephemeral().unwrap();
+        let swarm = build_swarm(&identity, &Config::ephemeral()).unwrap();
 
         let behaviour_any = swarm.behaviour();
-        let behaviour: &DissonanceBehaviour = behaviour_any;
+        let _behaviour: &DissonanceBehaviour = behaviour_any;
         // let _ = &behaviour.kademlia;
     }
+
+    #[tokio::test]
+    async fn test_two_swarms_exchange_chat() {
+        use futures::StreamExt;
+        use libp2p::{request_response, swarm::SwarmEvent};
+        use crate::network::behaviour::DissonanceEvent;
+        use crate::e2e::E2eKeys;
+        use crate::network::behaviours::chat::{ChatAck, ChatMessage, DeliveryStatus};
+
+        let alice_identity = NodeIdentity::generate_ephemeral().unwrap();
+        let bob_identity = NodeIdentity::generate_ephemeral().unwrap();
+        let mut alice = build_swarm(&alice_identity, &Config::ephemeral()).unwrap();
+        let mut bob = build_swarm(&bob_identity, &Config::ephemeral()).unwrap();
+
+        alice.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        let alice_addr = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = alice.select_next_some().await {
+                break address;
+            }
+        };
+        bob.dial(alice_addr).unwrap();
+        loop {
+            tokio::select! {
+                _ = alice.select_next_some() => {},
+                event = bob.select_next_some() => {
+                    if let SwarmEvent::ConnectionEstablished { .. } = event {
+                        break;
+                    }
+                },
+            }
+        }
+
+        let alice_keys = E2eKeys::from_identity(&alice_identity);
+        let bob_keys = E2eKeys::from_identity(&bob_identity);
+        let message = ChatMessage::new(bob_identity.peer_id(), "hello alice");
+        let message_id = message.id.clone();
+        bob.behaviour_mut().send_chat(&alice_identity.peer_id(), message.seal(&bob_keys, &alice_identity.peer_id()).unwrap());
+
+        let exchange = async {
+            loop {
+                tokio::select! {
+                    event = alice.select_next_some() => {
+                        if let SwarmEvent::Behaviour(DissonanceEvent::Chat(request_response::Event::Message {
+                            message: request_response::Message::Request { request, channel, .. }, ..
+                        })) = event {
+                            let message = request.open(&alice_keys).unwrap();
+                            assert_eq!(message.body, "hello alice");
+                            alice.behaviour_mut().acknowledge_chat(channel, ChatAck::delivered(&message.id)).unwrap();
+                        }
+                    },
+                    event = bob.select_next_some() => {
+                        if let SwarmEvent::Behaviour(DissonanceEvent::Chat(request_response::Event::Message {
+                            message: request_response::Message::Response { response, .. }, ..
+                        })) = event {
+                            break response;
+                        }
+                    },
+                }
+            }
+        };
+
+        let ack = tokio::time::timeout(std::time::Duration::from_secs(10), exchange).await.expect("Chat exchange timed out");
+        assert_eq!(ack.id, message_id);
+        assert_eq!(ack.status, DeliveryStatus::Delivered);
+    }
+
+    #[tokio::test]
+    async fn test_room_message_reaches_member() {
+        use futures::StreamExt;
+        use libp2p::swarm::SwarmEvent;
+        use crate::network::behaviour::DissonanceEvent;
+        use crate::network::behaviours::rooms::{RoomEvent, RoomMessage};
+
+        let alice_identity = NodeIdentity::generate_ephemeral().unwrap();
+        let bob_identity = NodeIdentity::generate_ephemeral().unwrap();
+        let mut alice = build_swarm(&alice_identity, &Config::ephemeral()).unwrap();
+        let mut bob = build_swarm(&bob_identity, &Config::ephemeral()).unwrap();
+
+        alice.behaviour_mut().join_room("general").unwrap();
+        bob.behaviour_mut().join_room("general").unwrap();
+        alice.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        let alice_addr = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = alice.select_next_some().await {
+                break address;
+            }
+        };
+        bob.dial(alice_addr).unwrap();
+
+        let exchange = async {
+            loop {
+                tokio::select! {
+                    event = alice.select_next_some() => {
+                        if let SwarmEvent::Behaviour(DissonanceEvent::Room(RoomEvent::MemberJoined { room, peer })) = event {
+                            assert_eq!(room, "general");
+                            assert_eq!(peer, bob_identity.peer_id());
+                            let message = RoomMessage::new(alice_identity.peer_id(), "general", "hello room");
+                            alice.behaviour_mut().publish_room_message(&message).unwrap();
+                        }
+                    },
+                    event = bob.select_next_some() => {
+                        if let SwarmEvent::Behaviour(DissonanceEvent::Room(RoomEvent::Message { message, .. })) = event {
+                            break message;
+                        }
+                    },
+                }
+            }
+        };
+
+        let message = tokio::time::timeout(std::time::Duration::from_secs(10), exchange).await.expect("Room message timed out");
+        assert_eq!(message.sender, alice_identity.peer_id());
+        assert_eq!(message.body, "hello room");
+    }
 }
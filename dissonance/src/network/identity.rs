@@ -1,10 +1,37 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use bip39::{Language, Mnemonic};
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, ChaCha20Poly1305, Key, Nonce};
 use ed25519_dalek::{SigningKey,VerifyingKey, SECRET_KEY_LENGTH, PUBLIC_KEY_LENGTH, KEYPAIR_LENGTH};
 use libp2p::{identity, PeerId};
 use rand::TryRngCore;
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use anyhow::{Context, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::config::default_data_dir;
+use crate::network::behaviours::succession::{SuccessionCertificate, SUCCESSION_FILE};
+use crate::store::write_atomic;
+
+/// Environment variable the identity passphrase is read from before prompting.
+pub const PASSPHRASE_ENV: &str = "DSN_IDENTITY_PASSPHRASE";
+
+const ENCRYPTED_IDENTITY_VERSION: u8 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+const BACKUP_CODE_PREFIX: &str = "DSN1:";
+const BACKUP_CHECKSUM_LENGTH: usize = 4;
+
+/// Outcome of `NodeIdentity::rotate`.
+pub struct Rotation{
+    pub identity: NodeIdentity,
+    pub backup: PathBuf,
+    pub certificate: SuccessionCertificate,
+}
 
 #[derive(Debug, Clone)]
 pub struct NodeIdentity{
@@ -18,24 +45,69 @@ struct StoredNodeIdentity{
     private_key_bytes: [u8; SECRET_KEY_LENGTH],
 }
 
+/// Argon2id cost parameters, stored next to the ciphertext so they can be raised later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams{
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams{
+    fn default() -> Self{
+        KdfParams{
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// The private key sealed with ChaCha20-Poly1305 under a key derived from the passphrase.
+#[derive(Serialize, Deserialize)]
+struct EncryptedNodeIdentity{
+    version: u8,
+    kdf: KdfParams,
+    salt: [u8; SALT_LENGTH],
+    nonce: [u8; NONCE_LENGTH],
+    ciphertext: Vec<u8>,
+}
+
+/// Identity files written before encryption at rest hold the bare key.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum IdentityFile{
+    Encrypted(EncryptedNodeIdentity),
+    Plaintext(StoredNodeIdentity),
+}
+
 impl NodeIdentity{
 
-    pub fn get_identity() -> Result<Self>{
-        let identity_path = Self::get_identity_path()?;
+    pub fn get_identity(passphrase: &str) -> Result<Self>{
+        Self::load_or_create(&Self::get_identity_path()?, passphrase)
+    }
 
+    /// Loads the identity stored at `identity_path`, generating and saving one on first use.
+    /// Plaintext files from older versions are re-written encrypted under `passphrase`.
+    pub fn load_or_create(identity_path: &Path, passphrase: &str) -> Result<Self>{
         if identity_path.exists(){
-            println!("loading existing identity from keypair: {}", identity_path.display());
-            Self::load_from_file(&identity_path)
+            tracing::info!("Loading existing identity from keypair: {}", identity_path.display());
+            let (identity, encrypted) = Self::read_file(identity_path, passphrase)?;
+            if !encrypted{
+                identity.save_to_file(identity_path, passphrase)?;
+                tracing::warn!("Upgraded plaintext identity at {} to an encrypted one", identity_path.display());
+            }
+            Ok(identity)
         }else{
-            println!("Generating new identity!");
-            let identity = Self::load_or_generate()?;
-            identity.save_to_file(&identity_path)?;
-            println!("Generated new identity and stored at: {}", identity_path.display());
+            tracing::info!("Generating new identity!");
+            let identity = Self::generate()?;
+            identity.save_to_file(identity_path, passphrase)?;
+            tracing::info!("Generated new identity and stored at: {}", identity_path.display());
             Ok(identity)
         }
     }
     pub fn generate_ephemeral() -> Result<Self> {
-        println!("⚠️ Generating ephemeral (in-memory) identity, not persisted to disk!");
+        tracing::warn!("Generating ephemeral (in-memory) identity, not persisted to disk!");
         let mut secret_bytes = [0u8; SECRET_KEY_LENGTH];
         rand::rngs::OsRng.try_fill_bytes(&mut secret_bytes)?;
         let signing_key: SigningKey = SigningKey::from_bytes(&secret_bytes);
@@ -45,58 +117,197 @@ impl NodeIdentity{
             .context("Failed to create libp2p public key for ephemeral identity")?;
         let peer_id = PeerId::from_public_key(&identity::PublicKey::from(lp2p_pub));
 
-        println!("Created ephemeral Node identity: {}", peer_id);
+        tracing::info!("Created ephemeral Node identity: {}", peer_id);
         Ok(NodeIdentity {
             signing_key,
             verifying_key,
             peer_id,
         })
     }
-    fn load_or_generate() -> Result<Self>{
+    fn generate() -> Result<Self>{
         let mut secret_bytes = [0u8; SECRET_KEY_LENGTH];
         rand::rngs::OsRng.try_fill_bytes(&mut secret_bytes)?;
         let signing_key: SigningKey = SigningKey::from_bytes(&secret_bytes);
         let verifying_key = signing_key.verifying_key();
         let lp2p_pub = identity::ed25519::PublicKey::try_from_bytes(&verifying_key.to_bytes()).context("Failed to cerate libp2p public key")?;
         let peer_id = PeerId::from_public_key(&identity::PublicKey::from(lp2p_pub));
-        println!("Created Node identity: {}", peer_id);        
-        Ok(NodeIdentity { signing_key:signing_key, verifying_key: verifying_key, peer_id: peer_id })
+        tracing::info!("Created Node identity: {}", peer_id);
+        Ok(NodeIdentity { signing_key, verifying_key, peer_id })
     }
 
-    fn save_to_file(&self, path: &Path) -> Result<()>{
+    /// Encrypts the private key under `passphrase` and writes it to `path`, readable by the owner only.
+    pub fn save_to_file(&self, path: &Path, passphrase: &str) -> Result<()>{
+        self.save_with_params(path, passphrase, KdfParams::default())
+    }
 
-        if let Some(parent) = path.parent(){
-            fs::create_dir_all(parent).context("Failed to create identity directory")?;
+    pub fn save_with_params(&self, path: &Path, passphrase: &str, kdf: KdfParams) -> Result<()>{
+        if passphrase.is_empty(){
+            bail!("Refusing to encrypt the identity with an empty passphrase");
         }
-        
+        let mut salt = [0u8; SALT_LENGTH];
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::rngs::OsRng.try_fill_bytes(&mut salt)?;
+        rand::rngs::OsRng.try_fill_bytes(&mut nonce)?;
+
+        let key = derive_key(passphrase, &salt, kdf)?;
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &self.signing_key.to_bytes(), aad: &identity_aad(kdf) })
+            .map_err(|_| anyhow!("Failed to encrypt identity"))?;
+        let stored = IdentityFile::Encrypted(EncryptedNodeIdentity{
+            version: ENCRYPTED_IDENTITY_VERSION,
+            kdf,
+            salt,
+            nonce,
+            ciphertext,
+        });
+        let content = serde_json::to_vec_pretty(&stored).context("failed to serialize identity")?;
+        write_private(path, &content)?;
+        tracing::info!("Node identity saved to: {}", path.display());
+        Ok(())
+    }
+
+    /// Writes the bare private key, for explicit exports. Still readable by the owner only.
+    pub fn save_plaintext(&self, path: &Path) -> Result<()>{
+        write_private(path, self.to_json()?.as_bytes())
+    }
+
+    /// Serializes the private key in the same format as the identity file.
+    pub fn to_json(&self) -> Result<String>{
         let stored = StoredNodeIdentity{
             private_key_bytes : self.signing_key.to_bytes(),
         };
+        serde_json::to_string_pretty(&stored).context("failed to serialize identity")
+    }
 
-        let content = serde_json::to_string_pretty(&stored).context("failed to serialize identity")?;
+    fn get_identity_path() -> Result<PathBuf>{
+        Ok(Self::identity_path(&default_data_dir()?))
+    }
 
-        fs::write(path, content).context("Failed to create identity file")?;
-        println!("Node identity save to: {}", path.display());
-        Ok(())
+    pub fn identity_path(data_dir: &Path) -> PathBuf{
+        data_dir.join("node-identity.json")
     }
 
-    fn get_identity_path() -> Result<PathBuf>{
-        let cf_dir = dirs::config_dir().context("Could not determine config directory")?;
-        Ok(cf_dir.join("dsn-chat").join("node-identity.json"))
+    /// Reads an identity file, decrypting it with `passphrase` unless it predates encryption.
+    pub fn load_from_file(path: &Path, passphrase: &str) -> Result<Self>{
+        Self::read_file(path, passphrase).map(|(identity, _)| identity)
     }
 
-    fn load_from_file(path: &Path) -> Result<Self>{
+    /// Whether the file at `path` is encrypted, i.e. whether loading it needs a passphrase.
+    pub fn is_encrypted(path: &Path) -> Result<bool>{
         let content = fs::read_to_string(path).context("Failed to read identity file")?;
-        let stored :StoredNodeIdentity = serde_json::from_str(&content).context("Failed to parse identity")?;
+        let stored: IdentityFile = serde_json::from_str(&content).context("Failed to parse identity")?;
+        Ok(matches!(stored, IdentityFile::Encrypted(_)))
+    }
 
-        let signing_key = SigningKey::from_bytes(&stored.private_key_bytes);
+    fn read_file(path: &Path, passphrase: &str) -> Result<(Self, bool)>{
+        let content = fs::read_to_string(path).context("Failed to read identity file")?;
+        match serde_json::from_str(&content).context("Failed to parse identity")? {
+            IdentityFile::Plaintext(stored) => Ok((Self::from_private_key(&stored.private_key_bytes)?, false)),
+            IdentityFile::Encrypted(stored) => {
+                if stored.version != ENCRYPTED_IDENTITY_VERSION{
+                    bail!("Unsupported identity file version {}", stored.version);
+                }
+                let key = derive_key(passphrase, &stored.salt, stored.kdf)?;
+                let private_key = ChaCha20Poly1305::new(Key::from_slice(&key))
+                    .decrypt(Nonce::from_slice(&stored.nonce), Payload { msg: &stored.ciphertext, aad: &identity_aad(stored.kdf) })
+                    .map_err(|_| anyhow!("Wrong passphrase for {}", path.display()))?;
+                let private_key: [u8; SECRET_KEY_LENGTH] = private_key.try_into().map_err(|_| anyhow!("Identity file holds a malformed key"))?;
+                Ok((Self::from_private_key(&private_key)?, true))
+            },
+        }
+    }
+
+    pub fn from_json(content: &str) -> Result<Self>{
+        let stored :StoredNodeIdentity = serde_json::from_str(content).context("Failed to parse identity")?;
+        Self::from_private_key(&stored.private_key_bytes)
+    }
+
+    /// The private key as a 24-word BIP39 phrase, for writing down as a paper backup.
+    pub fn to_mnemonic(&self) -> String{
+        Mnemonic::from_entropy(&self.signing_key.to_bytes()).expect("32 bytes is valid BIP39 entropy").to_string()
+    }
+
+    /// Restores the identity written out by `to_mnemonic`. The phrase checksum catches mistyped words.
+    pub fn from_mnemonic(phrase: &str) -> Result<Self>{
+        let mnemonic = Mnemonic::parse_in(Language::English, phrase.to_lowercase()).map_err(|e| anyhow!("Invalid recovery phrase: {e}"))?;
+        let private_key: [u8; SECRET_KEY_LENGTH] = mnemonic.to_entropy().try_into()
+            .map_err(|_| anyhow!("Recovery phrase must have 24 words"))?;
+        Self::from_private_key(&private_key)
+    }
+
+    /// The private key as `DSN1:` followed by upper-case hex and a checksum. It only uses
+    /// characters from the QR alphanumeric set, so it fits a small code.
+    pub fn to_backup_code(&self) -> String{
+        let key = self.signing_key.to_bytes();
+        let hex: String = key.iter().chain(&backup_checksum(&key)).map(|byte| format!("{byte:02X}")).collect();
+        format!("{BACKUP_CODE_PREFIX}{hex}")
+    }
+
+    pub fn from_backup_code(code: &str) -> Result<Self>{
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_uppercase();
+        let hex = code.strip_prefix(BACKUP_CODE_PREFIX).context("Backup code must start with DSN1:")?;
+        if hex.len() != (SECRET_KEY_LENGTH + BACKUP_CHECKSUM_LENGTH) * 2 || !hex.is_ascii(){
+            bail!("Backup code has the wrong length");
+        }
+        let bytes = (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .context("Backup code is not valid hex")?;
+        let (key, checksum) = bytes.split_at(SECRET_KEY_LENGTH);
+        let key: [u8; SECRET_KEY_LENGTH] = key.try_into().expect("split at the key length");
+        if checksum != backup_checksum(&key){
+            bail!("Backup code checksum does not match; check for typos");
+        }
+        Self::from_private_key(&key)
+    }
+
+    fn from_private_key(private_key_bytes: &[u8; SECRET_KEY_LENGTH]) -> Result<Self>{
+        let signing_key = SigningKey::from_bytes(private_key_bytes);
         let verifying_key = signing_key.verifying_key();
 
         let lp2p_pub = identity::ed25519::PublicKey::try_from_bytes(&verifying_key.to_bytes()).context("Could not create lp2p public key")?;
         let peer_id = PeerId::from_public_key(&identity::PublicKey::from(lp2p_pub));
 
-        println!("Created node identity: {}", peer_id);
-        Ok(NodeIdentity { signing_key:signing_key, verifying_key: verifying_key, peer_id: peer_id })
+        tracing::info!("Created node identity: {}", peer_id);
+        Ok(NodeIdentity { signing_key, verifying_key, peer_id })
+    }
+
+    /// Moves the identity file at `path` aside and stores `replacement` in its place.
+    /// Returns where the previous identity was backed up to.
+    pub fn replace_file(path: &Path, replacement: &NodeIdentity, passphrase: &str) -> Result<PathBuf>{
+        if !path.exists(){
+            bail!("No identity at {}", path.display());
+        }
+        // Written out in full before the live file is touched, so a failed save leaves it in place.
+        let staged = path.with_extension("json.new");
+        replacement.save_to_file(&staged, passphrase)?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let backup = path.with_extension(format!("json.{timestamp}.bak"));
+        if let Err(e) = fs::rename(path, &backup) {
+            let _ = fs::remove_file(&staged);
+            return Err(e).context("Failed to back up previous identity");
+        }
+        if let Err(e) = fs::rename(&staged, path) {
+            let _ = fs::rename(&backup, path);
+            return Err(e).context("Failed to install new identity");
+        }
+        Ok(backup)
+    }
+
+    /// Generates a fresh identity to replace the one at `path`, keeping the old key as a backup.
+    /// The old key signs a succession certificate for the new one, which is appended to the
+    /// chain next to the identity file so the node can announce it to its contacts.
+    pub fn rotate(path: &Path, passphrase: &str) -> Result<Rotation>{
+        let previous = Self::load_from_file(path, passphrase)?;
+        let identity = Self::generate()?;
+        let certificate = SuccessionCertificate::issue(&previous, &identity);
+        let backup = Self::replace_file(path, &identity, passphrase)?;
+        // Without the certificate contacts could not follow us to the new key, so put the old one back.
+        if let Err(e) = certificate.append_to(&path.with_file_name(SUCCESSION_FILE)) {
+            fs::rename(&backup, path).context("Failed to restore previous identity")?;
+            return Err(e).context("Failed to record succession certificate");
+        }
+        Ok(Rotation { identity, backup, certificate })
     }
 
     pub fn peer_id(&self) -> PeerId{
@@ -117,6 +328,35 @@ impl NodeIdentity{
 
 }
 
+fn derive_key(passphrase: &str, salt: &[u8], kdf: KdfParams) -> Result<[u8; 32]>{
+    let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32))
+        .map_err(|e| anyhow!("Invalid key derivation parameters: {e}"))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive identity key: {e}"))?;
+    Ok(key)
+}
+
+/// Binds the ciphertext to the format version and KDF costs so neither can be downgraded in place.
+fn identity_aad(kdf: KdfParams) -> Vec<u8>{
+    let mut aad = vec![ENCRYPTED_IDENTITY_VERSION];
+    aad.extend_from_slice(&kdf.memory_kib.to_be_bytes());
+    aad.extend_from_slice(&kdf.iterations.to_be_bytes());
+    aad.extend_from_slice(&kdf.parallelism.to_be_bytes());
+    aad
+}
+
+fn backup_checksum(key: &[u8; SECRET_KEY_LENGTH]) -> [u8; BACKUP_CHECKSUM_LENGTH]{
+    let digest = Sha256::digest(key);
+    digest[..BACKUP_CHECKSUM_LENGTH].try_into().expect("digest is longer than the checksum")
+}
+
+fn write_private(path: &Path, content: &[u8]) -> Result<()>{
+    write_atomic(path, content)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).context("Failed to restrict identity file permissions")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,7 +368,7 @@ mod tests {
 
     #[test]
     fn test_generate_and_serialize_identity() {
-        let identity = NodeIdentity::load_or_generate().expect("Failed to create identity");
+        let identity = NodeIdentity::generate().expect("Failed to create identity");
         let stored = StoredNodeIdentity {
             private_key_bytes: identity.signing_key.to_bytes(),
         };
@@ -142,20 +382,96 @@ mod tests {
         let temp = tempdir().unwrap();
         let path = test_identity_path(&temp);
 
-        let identity = NodeIdentity::load_or_generate().expect("Failed to generate identity");
-        identity.save_to_file(&path).expect("Failed to save identity");
+        let identity = NodeIdentity::generate().expect("Failed to generate identity");
+        identity.save_to_file(&path, "hunter2").expect("Failed to save identity");
 
         assert!(path.exists());
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(NodeIdentity::is_encrypted(&path).unwrap());
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("private_key_bytes"));
 
-        let loaded = NodeIdentity::load_from_file(&path).expect("Failed to load identity");
+        assert!(NodeIdentity::load_from_file(&path, "wrong").is_err());
+        let loaded = NodeIdentity::load_from_file(&path, "hunter2").expect("Failed to load identity");
         assert_eq!(identity.peer_id, loaded.peer_id);
         assert_eq!(identity.signing_key.to_bytes(), loaded.signing_key.to_bytes());
         assert_eq!(identity.verifying_key.to_bytes(), loaded.verifying_key.to_bytes());
     }
 
+    #[test]
+    fn test_rotate_keeps_backup() {
+        let temp = tempdir().unwrap();
+        let path = test_identity_path(&temp);
+        let original = NodeIdentity::load_or_create(&path, "hunter2").unwrap();
+
+        let Rotation { identity: rotated, backup, certificate } = NodeIdentity::rotate(&path, "hunter2").expect("Failed to rotate identity");
+        assert_ne!(rotated.peer_id, original.peer_id);
+        assert_eq!(NodeIdentity::load_from_file(&path, "hunter2").unwrap().peer_id, rotated.peer_id);
+        assert_eq!(NodeIdentity::load_from_file(&backup, "hunter2").unwrap().peer_id, original.peer_id);
+
+        certificate.verify().unwrap();
+        assert_eq!(certificate.previous().unwrap(), original.peer_id);
+        assert_eq!(certificate.successor().unwrap(), rotated.peer_id);
+        let chain = SuccessionCertificate::load_chain(&SuccessionCertificate::path(temp.path())).unwrap();
+        assert_eq!(chain, vec![certificate]);
+
+        let imported = NodeIdentity::from_json(&original.to_json().unwrap()).unwrap();
+        assert_eq!(imported.peer_id, original.peer_id);
+    }
+
+    #[test]
+    fn test_failed_rotation_keeps_previous_identity() {
+        let temp = tempdir().unwrap();
+        let path = test_identity_path(&temp);
+        let original = NodeIdentity::load_or_create(&path, "hunter2").unwrap();
+        fs::create_dir(path.with_file_name(SUCCESSION_FILE)).unwrap();
+
+        assert!(NodeIdentity::rotate(&path, "hunter2").is_err());
+        assert_eq!(NodeIdentity::load_from_file(&path, "hunter2").unwrap().peer_id, original.peer_id);
+    }
+
+    #[test]
+    fn test_plaintext_identity_is_upgraded() {
+        let temp = tempdir().unwrap();
+        let path = test_identity_path(&temp);
+        let identity = NodeIdentity::generate().unwrap();
+        fs::write(&path, identity.to_json().unwrap()).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let loaded = NodeIdentity::load_or_create(&path, "hunter2").unwrap();
+        assert_eq!(loaded.peer_id, identity.peer_id);
+        assert!(NodeIdentity::is_encrypted(&path).unwrap());
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(NodeIdentity::load_from_file(&path, "hunter2").unwrap().peer_id, identity.peer_id);
+    }
+
+    #[test]
+    fn test_mnemonic_and_backup_code_roundtrip() {
+        let identity = NodeIdentity::generate().unwrap();
+
+        let phrase = identity.to_mnemonic();
+        assert_eq!(phrase.split_whitespace().count(), 24);
+        let restored = NodeIdentity::from_mnemonic(&format!("  {}\n", phrase.to_uppercase())).unwrap();
+        assert_eq!(restored.peer_id, identity.peer_id);
+
+        // A fixed key keeps the checksum failure deterministic.
+        let fixed = NodeIdentity::from_private_key(&[7u8; SECRET_KEY_LENGTH]).unwrap().to_mnemonic();
+        let mut words: Vec<&str> = fixed.split_whitespace().collect();
+        words[0] = if words[0] == "abandon" { "ability" } else { "abandon" };
+        assert!(NodeIdentity::from_mnemonic(&words.join(" ")).is_err(), "A wrong word should fail the checksum");
+        assert!(NodeIdentity::from_mnemonic(&phrase.split_whitespace().take(12).collect::<Vec<_>>().join(" ")).is_err());
+
+        let code = identity.to_backup_code();
+        assert!(code.chars().all(|c| c.is_ascii_digit() || c.is_ascii_uppercase() || c == ':'));
+        assert_eq!(NodeIdentity::from_backup_code(&code.to_lowercase()).unwrap().peer_id, identity.peer_id);
+        let mut typo = code.clone().into_bytes();
+        typo[10] = if typo[10] == b'0' { b'1' } else { b'0' };
+        assert!(NodeIdentity::from_backup_code(&String::from_utf8(typo).unwrap()).is_err());
+    }
+
     #[test]
     fn test_pub_key_bytes() {
-        let identity = NodeIdentity::load_or_generate().unwrap();
+        let identity = NodeIdentity::generate().unwrap();
         let pub_bytes = identity.pub_key_bytes();
         assert_eq!(pub_bytes.len(), PUBLIC_KEY_LENGTH);
         assert_eq!(pub_bytes, identity.verifying_key.to_bytes());
@@ -163,7 +479,7 @@ mod tests {
 
     #[test]
     fn test_to_lp2p_keypair() {
-        let identity = NodeIdentity::load_or_generate().unwrap();
+        let identity = NodeIdentity::generate().unwrap();
         let keypair = identity.to_lp2p_keypair().expect("Failed to convert to libp2p keypair");
         let peer_id = PeerId::from_public_key(&keypair.public());
         assert_eq!(peer_id, identity.peer_id);
@@ -3,5 +3,6 @@ pub mod transport;
 pub mod builder;
 pub mod behaviour;
 pub mod behaviours;
+pub mod bootstrap;
 
 pub use identity::NodeIdentity;
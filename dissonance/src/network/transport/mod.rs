@@ -2,4 +2,7 @@ pub mod tcp;
 
 pub mod noise;
 
-pub mod yamux;
\ No newline at end of file
+pub mod yamux;
+pub mod quic;
+
+pub mod websocket;
@@ -0,0 +1,42 @@
+use libp2p::{multiaddr::Protocol, quic::Config as QuicConfig, Multiaddr};
+
+use crate::config::QuicOptions;
+
+pub fn build_quic_config(mut quic_config: QuicConfig, options: &QuicOptions) -> QuicConfig{
+    quic_config.handshake_timeout = options.handshake_timeout;
+    quic_config.max_idle_timeout = u32::try_from(options.max_idle_timeout.as_millis()).unwrap_or(u32::MAX);
+    quic_config.keep_alive_interval = options.keep_alive_interval;
+    quic_config.max_concurrent_stream_limit = options.max_concurrent_streams;
+    quic_config
+}
+
+pub fn is_quic(address: &Multiaddr) -> bool{
+    address.iter().any(|protocol| matches!(protocol, Protocol::QuicV1))
+}
+
+/// Orders `addresses` so QUIC is tried before TCP: it needs a single round trip to set up
+/// encryption and multiplexing and has no head-of-line blocking between streams.
+pub fn prefer_quic(addresses: &mut [Multiaddr]){
+    addresses.sort_by_key(|address| !is_quic(address));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quic_addresses_come_first() {
+        let mut addresses: Vec<Multiaddr> = [
+            "/ip4/127.0.0.1/tcp/4001",
+            "/ip4/127.0.0.1/udp/4001/quic-v1",
+            "/ip6/::1/tcp/4001",
+            "/ip6/::1/udp/4001/quic-v1",
+        ].into_iter().map(|address| address.parse().unwrap()).collect();
+        prefer_quic(&mut addresses);
+        let quic = addresses.iter().take_while(|address| is_quic(address)).count();
+        assert_eq!(quic, 2);
+        // Otherwise the order is kept.
+        assert_eq!(addresses[0], "/ip4/127.0.0.1/udp/4001/quic-v1".parse::<Multiaddr>().unwrap());
+        assert_eq!(addresses[2], "/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap());
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use libp2p::{
+    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade::Version},
+    dns, identity, tcp, websocket, PeerId, Transport,
+};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+
+use crate::config::{WebsocketOptions, YamuxOptions};
+use super::{noise::build_noise_config, tcp::build_tcp_config, yamux::build_yamux_config};
+
+/// WebSocket over TCP, for nodes behind HTTP reverse proxies or firewalls that only let web
+/// traffic through. Hostnames are resolved underneath the WebSocket layer so `/dns4/<host>/tcp/443/wss`
+/// keeps the name for TLS, which only holds if no other DNS transport wraps this one.
+/// `/wss` listeners need a certificate in `options`.
+pub fn build_websocket_transport(keypair: &identity::Keypair, yamux: &YamuxOptions, options: &WebsocketOptions) -> Result<Boxed<(PeerId, StreamMuxerBox)>>{
+    let tcp = tcp::tokio::Transport::new(build_tcp_config());
+    let mut websocket = websocket::Config::new(dns::tokio::Transport::system(tcp).context("Failed to read the system DNS configuration")?);
+    if let Some(tls) = load_tls_config(options)? {
+        websocket.set_tls_config(tls);
+    }
+    let transport = websocket
+        .upgrade(Version::V1Lazy)
+        .authenticate(build_noise_config(keypair)?)
+        .multiplex(build_yamux_config(yamux))
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+        .boxed();
+    Ok(transport)
+}
+
+fn load_tls_config(options: &WebsocketOptions) -> Result<Option<websocket::tls::Config>>{
+    let server = match (&options.tls_certificate, &options.tls_private_key) {
+        (Some(certificate), Some(private_key)) => Some((certificate, private_key)),
+        (None, None) => None,
+        _ => anyhow::bail!("websocket.tls_certificate and websocket.tls_private_key must be set together"),
+    };
+    if server.is_none() && options.tls_trusted_certificates.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = websocket::tls::Config::builder();
+    if let Some((certificate, private_key)) = server {
+        let chain = load_certificates(certificate)?;
+        let key = PrivateKeyDer::from_pem_file(private_key)
+            .with_context(|| format!("Failed to read a PEM private key from {}", private_key.display()))?;
+        let key = websocket::tls::PrivateKey::new(key.secret_der().to_vec());
+        builder.server(key, chain).context("Invalid TLS certificate or key")?;
+    }
+    for path in &options.tls_trusted_certificates {
+        for certificate in load_certificates(path)? {
+            builder.add_trust(&certificate).with_context(|| format!("Invalid trusted certificate in {}", path.display()))?;
+        }
+    }
+    Ok(Some(builder.finish()))
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<websocket::tls::Certificate>>{
+    let chain = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read PEM certificates from {}", path.display()))?;
+    if chain.is_empty() {
+        anyhow::bail!("No certificates in {}", path.display());
+    }
+    Ok(chain.into_iter().map(|certificate| websocket::tls::Certificate::new(certificate.to_vec())).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_needs_certificate_and_key() {
+        assert!(load_tls_config(&WebsocketOptions::default()).unwrap().is_none());
+        let options = WebsocketOptions { tls_certificate: Some("cert.pem".into()), ..WebsocketOptions::default() };
+        assert!(load_tls_config(&options).is_err());
+        let options = WebsocketOptions { tls_certificate: Some("/nonexistent/cert.pem".into()), tls_private_key: Some("/nonexistent/key.pem".into()), ..WebsocketOptions::default() };
+        assert!(load_tls_config(&options).is_err());
+        let options = WebsocketOptions { tls_trusted_certificates: vec!["/nonexistent/ca.pem".into()], ..WebsocketOptions::default() };
+        assert!(load_tls_config(&options).is_err());
+    }
+}
@@ -1,8 +1,10 @@
 use libp2p::yamux::Config;
 
-pub fn build_yamux_config() -> Config{
+use crate::config::YamuxOptions;
+
+pub fn build_yamux_config(options: &YamuxOptions) -> Config{
     let mut yamux_config = Config::default();
-    yamux_config.set_max_num_streams(256);
+    yamux_config.set_max_num_streams(options.max_num_streams);
 
     yamux_config   
 }
\ No newline at end of file
@@ -0,0 +1,110 @@
+use std::time::{Duration, SystemTime};
+
+use libp2p::{autonat::NatStatus, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::config::duration_secs;
+use crate::network::behaviours::chat::{DeliveryStatus, MessageId};
+use crate::reputation::Standing;
+
+/// High-level events broadcast by a running node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeEvent {
+    ListeningOn { address: Multiaddr },
+    PeerConnected { peer: PeerId, address: Multiaddr },
+    PeerDisconnected { peer: PeerId },
+    PeerDiscovered { peer: PeerId, address: Multiaddr },
+    ChatMessage { peer: PeerId, id: MessageId, timestamp: SystemTime, body: String },
+    ChatAck { peer: PeerId, id: MessageId, status: DeliveryStatus },
+    ChatFailed { peer: PeerId, error: String },
+    /// A contact proved it replaced its key; everything known about `previous` now belongs to `successor`.
+    PeerRotated { previous: PeerId, successor: PeerId },
+    /// Alert: a contact whose key the user had verified now uses a different one.
+    ContactKeyChanged { previous: PeerId, successor: PeerId },
+    /// Alert: `peer` answered at, or claimed to listen on, an address first answered by `pinned`.
+    AddressMismatch { address: Multiaddr, pinned: PeerId, peer: PeerId, blocked: bool },
+    /// The peer runs a version this node cannot talk to and was disconnected.
+    PeerIncompatible { peer: PeerId, agent_version: String, reason: String },
+    /// A relay accepted our reservation; peers can now reach us through it.
+    RelayReserved { relay: PeerId },
+    /// Hole punching replaced the relayed connection to `peer` with a direct one.
+    DirectConnectionUpgraded { peer: PeerId },
+    /// Hole punching to `peer` failed; traffic keeps going through the relay.
+    DirectConnectionFailed { peer: PeerId, error: String },
+    /// AutoNAT settled on a different answer to whether peers can dial us.
+    ReachabilityChanged { reachability: Reachability },
+    /// Peers can reach us at `address`, as confirmed by AutoNAT or configured.
+    ExternalAddressConfirmed { address: Multiaddr },
+    ExternalAddressExpired { address: Multiaddr },
+    /// The routing table was refreshed from the network; the next refresh is due in `next_in`.
+    BootstrapSucceeded {
+        #[serde(with = "duration_secs")]
+        next_in: Duration,
+    },
+    /// Bootstrapping failed `failures` times in a row and is retried in `retry_in`.
+    BootstrapFailed {
+        reason: String,
+        failures: u32,
+        #[serde(with = "duration_secs")]
+        retry_in: Duration,
+    },
+    /// Kademlia switched between serving the DHT and only querying it.
+    DhtModeChanged { server: bool },
+    /// The peer's reputation moved it to a different standing.
+    StandingChanged { peer: PeerId, standing: Standing, score: f64 },
+    RoomMessage { room: String, sender: PeerId, id: MessageId, timestamp: SystemTime, body: String },
+    RoomMemberJoined { room: String, peer: PeerId },
+    RoomMemberLeft { room: String, peer: PeerId },
+    /// A subscriber fell behind and `missed` events were dropped.
+    Lagged { missed: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub peer_id: PeerId,
+    /// Hex-encoded X25519 key peers seal messages to.
+    pub e2e_public_key: String,
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Confirmed publicly reachable addresses.
+    pub external_addrs: Vec<Multiaddr>,
+    pub reachability: Reachability,
+}
+
+/// Whether peers can dial this node directly, as measured by AutoNAT.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reachability {
+    Public { address: Multiaddr },
+    Private,
+    Unknown,
+}
+
+impl From<NatStatus> for Reachability {
+    fn from(status: NatStatus) -> Self {
+        match status {
+            NatStatus::Public(address) => Reachability::Public { address },
+            NatStatus::Private => Reachability::Private,
+            NatStatus::Unknown => Reachability::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerSummary {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+    pub agent_version: Option<String>,
+    pub last_seen: SystemTime,
+    pub connected: bool,
+    /// The user compared safety numbers for the peer's current key.
+    pub verified: bool,
+    /// Reputation score; see `reputation::Signal` for what moves it.
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomSummary {
+    pub room: String,
+    pub members: Vec<PeerId>,
+}
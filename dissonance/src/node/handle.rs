@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use libp2p::{Multiaddr, PeerId};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::network::behaviours::chat::{ChatAck, MessageId};
+
+use super::events::{NodeEvent, NodeInfo, PeerSummary, RoomSummary};
+
+pub(crate) type Reply<T> = oneshot::Sender<Result<T>>;
+
+#[derive(Debug)]
+pub(crate) enum NodeCommand {
+    Info(Reply<NodeInfo>),
+    Peers(Reply<Vec<PeerSummary>>),
+    AddPeerAddress { peer: PeerId, address: Multiaddr, reply: Reply<()> },
+    Dial { address: Multiaddr, reply: Reply<PeerId> },
+    Send { peer: PeerId, text: String, reply: Reply<ChatAck> },
+    JoinRoom { room: String, reply: Reply<bool> },
+    LeaveRoom { room: String, reply: Reply<bool> },
+    Publish { room: String, text: String, reply: Reply<MessageId> },
+    Rooms(Reply<Vec<RoomSummary>>),
+    SafetyNumber { peer: PeerId, reply: Reply<String> },
+    Verify { peer: PeerId, verified: bool, reply: Reply<()> },
+    Unpin { address: Multiaddr, reply: Reply<Option<PeerId>> },
+    Prune { max_age: Duration, reply: Reply<usize> },
+    Shutdown(Reply<()>),
+}
+
+/// Cloneable handle to a node started with `Node::spawn`. The node stops once `shutdown` is
+/// called or every handle has been dropped.
+#[derive(Debug, Clone)]
+pub struct NodeHandle {
+    peer_id: PeerId,
+    commands: mpsc::Sender<NodeCommand>,
+    events: broadcast::Sender<NodeEvent>,
+}
+
+impl NodeHandle {
+    pub(crate) fn new(peer_id: PeerId, commands: mpsc::Sender<NodeCommand>, events: broadcast::Sender<NodeEvent>) -> Self {
+        NodeHandle { peer_id, commands, events }
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    pub async fn info(&self) -> Result<NodeInfo> {
+        self.request(NodeCommand::Info).await
+    }
+
+    pub async fn peers(&self) -> Result<Vec<PeerSummary>> {
+        self.request(NodeCommand::Peers).await
+    }
+
+    /// Tells the node where `peer` can be reached, e.g. before `send`.
+    pub async fn add_peer_address(&self, peer: PeerId, address: Multiaddr) -> Result<()> {
+        self.request(|reply| NodeCommand::AddPeerAddress { peer, address, reply }).await
+    }
+
+    /// Connects to `address` and resolves with the peer on the other end.
+    pub async fn dial(&self, address: Multiaddr) -> Result<PeerId> {
+        self.request(|reply| NodeCommand::Dial { address, reply }).await
+    }
+
+    /// Seals `text` for `peer`, sends it and resolves with the recipient's acknowledgement.
+    pub async fn send(&self, peer: PeerId, text: impl Into<String>) -> Result<ChatAck> {
+        let text = text.into();
+        self.request(|reply| NodeCommand::Send { peer, text, reply }).await
+    }
+
+    pub async fn join_room(&self, room: impl Into<String>) -> Result<bool> {
+        let room = room.into();
+        self.request(|reply| NodeCommand::JoinRoom { room, reply }).await
+    }
+
+    pub async fn leave_room(&self, room: impl Into<String>) -> Result<bool> {
+        let room = room.into();
+        self.request(|reply| NodeCommand::LeaveRoom { room, reply }).await
+    }
+
+    pub async fn publish(&self, room: impl Into<String>, text: impl Into<String>) -> Result<MessageId> {
+        let (room, text) = (room.into(), text.into());
+        self.request(|reply| NodeCommand::Publish { room, text, reply }).await
+    }
+
+    pub async fn rooms(&self) -> Result<Vec<RoomSummary>> {
+        self.request(NodeCommand::Rooms).await
+    }
+
+    /// The code to compare with `peer` before marking it verified.
+    pub async fn safety_number(&self, peer: PeerId) -> Result<String> {
+        self.request(|reply| NodeCommand::SafetyNumber { peer, reply }).await
+    }
+
+    /// Marks `peer`'s current key as verified, or withdraws that when `verified` is false.
+    pub async fn set_verified(&self, peer: PeerId, verified: bool) -> Result<()> {
+        self.request(|reply| NodeCommand::Verify { peer, verified, reply }).await
+    }
+
+    /// Forgets which peer `address` is pinned to and resolves with that peer, if any.
+    pub async fn unpin_address(&self, address: Multiaddr) -> Result<Option<PeerId>> {
+        self.request(|reply| NodeCommand::Unpin { address, reply }).await
+    }
+
+    /// Forgets peers not seen within `max_age` and resolves with how many were removed.
+    pub async fn prune_peers(&self, max_age: Duration) -> Result<usize> {
+        self.request(|reply| NodeCommand::Prune { max_age, reply }).await
+    }
+
+    /// Flushes the stores and stops the node.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.request(NodeCommand::Shutdown).await
+    }
+
+    async fn request<T>(&self, command: impl FnOnce(Reply<T>) -> NodeCommand) -> Result<T> {
+        let (reply, response) = oneshot::channel();
+        self.commands.send(command(reply)).await.map_err(|_| anyhow!("Node has stopped"))?;
+        response.await.map_err(|_| anyhow!("Node stopped before answering"))?
+    }
+}
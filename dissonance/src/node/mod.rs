@@ -0,0 +1,7 @@
+pub mod events;
+pub mod handle;
+pub mod runtime;
+
+pub use events::{NodeEvent, NodeInfo, PeerSummary, Reachability, RoomSummary};
+pub use handle::NodeHandle;
+pub use runtime::Node;
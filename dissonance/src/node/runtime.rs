@@ -0,0 +1,1426 @@
+use std::{collections::{HashMap, HashSet}, time::{Instant, SystemTime}};
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use libp2p::{
+    identify::{Event as IdentifyEvent, Info as IdentifyInfo},
+    kad::{Event as KademliaEvent, GetRecordOk, InboundRequest, QueryId, QueryResult},
+    mdns::Event as MdnsEvent,
+    autonat, dcutr, kad::Mode as KademliaMode, relay,
+    request_response::{self, OutboundRequestId},
+    core::transport::ListenerId,
+    core::{transport::TransportError, upgrade::{NegotiationError, ProtocolError}},
+    noise,
+    swarm::{dial_opts::DialOpts, ConnectionId, DialError, Swarm, SwarmEvent},
+    Multiaddr, PeerId,
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::Interval,
+};
+
+use crate::config::{Config, TofuMode};
+use crate::e2e::E2eKeys;
+use crate::network::behaviour::{DissonanceBehaviour, DissonanceEvent};
+use crate::network::behaviours::identify::{check_compatibility, Compatibility};
+use crate::network::behaviours::relay::{circuit_listen_addr, is_relayed};
+use crate::network::behaviours::chat::{ChatAck, ChatEvent, ChatMessage, DeliveryStatus};
+use crate::network::behaviours::rooms::{RoomEvent, RoomMessage};
+use crate::network::behaviours::succession::{SuccessionAck, SuccessionCertificate, SuccessionEvent};
+use crate::network::bootstrap::{BootstrapEvent, Bootstrapper};
+use crate::network::builder::build_swarm;
+use crate::network::transport::quic::prefer_quic;
+use crate::reputation::{ReputationConfig, Signal, Standing};
+use crate::store::{PinCheck, PeerStore};
+use crate::verification::safety_number_for;
+use crate::NodeIdentity;
+
+use super::events::{NodeEvent, NodeInfo, PeerSummary, Reachability, RoomSummary};
+use super::handle::{NodeCommand, NodeHandle, Reply};
+
+const COMMAND_QUEUE: usize = 64;
+const EVENT_QUEUE: usize = 256;
+
+/// The swarm together with the stores and schedulers around it. Driven by `run`, normally on its
+/// own task via `spawn`.
+pub struct Node {
+    swarm: Swarm<DissonanceBehaviour>,
+    peer_store: PeerStore,
+    e2e_keys: E2eKeys,
+    bootstrapper: Bootstrapper,
+    records_republished: bool,
+    /// Certificates leading from our earlier identities to the current one, announced to every peer we meet.
+    successions: Vec<SuccessionCertificate>,
+    succession_queries: HashSet<QueryId>,
+    tofu: TofuMode,
+    reputation: ReputationConfig,
+    /// When each connected peer's first open connection was established, for uptime credit.
+    connected_since: HashMap<PeerId, Instant>,
+    /// `/p2p-circuit` addresses to hold reservations on, and the listeners currently doing so.
+    relay_circuits: Vec<Multiaddr>,
+    relay_listeners: HashMap<ListenerId, Multiaddr>,
+    /// External addresses from the config, kept even when AutoNAT finds us unreachable.
+    configured_external_addrs: Vec<Multiaddr>,
+    flush_interval: Interval,
+    commands: mpsc::Receiver<NodeCommand>,
+    events: broadcast::Sender<NodeEvent>,
+    pending_dials: HashMap<ConnectionId, Reply<libp2p::PeerId>>,
+    pending_sends: HashMap<OutboundRequestId, Reply<ChatAck>>,
+}
+
+impl Node {
+    /// Starts a node on a new tokio task and returns a handle to it.
+    pub fn spawn(config: Config, identity: NodeIdentity) -> Result<NodeHandle> {
+        let (node, handle) = Self::new(&config, &identity)?;
+        tokio::spawn(async move {
+            if let Err(e) = node.run().await {
+                tracing::error!("Node stopped with an error: {e:#}");
+            }
+        });
+        Ok(handle)
+    }
+
+    /// Builds the swarm, opens the stores and starts listening. Must be called within a tokio runtime.
+    pub fn new(config: &Config, identity: &NodeIdentity) -> Result<(Self, NodeHandle)> {
+        let mut peer_store = if config.storage.ephemeral_peer_store {
+            PeerStore::new()
+        } else {
+            PeerStore::open(&PeerStore::store_path(&config.data_dir()?))?
+        };
+        let mut swarm = build_swarm(identity, config)?;
+        tracing::info!("Local peer ID: {}", swarm.local_peer_id());
+
+        for address in &config.network.listen_addrs {
+            swarm.listen_on(address.clone())?;
+        }
+        for address in &config.network.external_addrs {
+            swarm.add_external_address(address.clone());
+        }
+        let relay_circuits = config.relay.relays.iter().map(circuit_listen_addr).collect::<Result<Vec<_>>>()?;
+
+        let bootstrapper = Bootstrapper::new(config.bootstrap.clone());
+        let seeded = bootstrapper.seed(swarm.behaviour_mut(), &mut peer_store);
+        tracing::info!("[BOOTSTRAP] Seeded Kademlia with {} known addresses", seeded);
+
+        let successions = load_successions(config, identity);
+        for certificate in &successions {
+            if let Err(e) = swarm.behaviour_mut().publish_succession(certificate) {
+                tracing::warn!("[SUCCESSION] Could not publish succession certificate: {e:#}");
+            }
+        }
+
+        let (command_sender, commands) = mpsc::channel(COMMAND_QUEUE);
+        let (events, _) = broadcast::channel(EVENT_QUEUE);
+        let handle = NodeHandle::new(identity.peer_id(), command_sender, events.clone());
+        let mut node = Node {
+            swarm,
+            peer_store,
+            e2e_keys: E2eKeys::from_identity(identity),
+            bootstrapper,
+            records_republished: false,
+            successions,
+            succession_queries: HashSet::new(),
+            tofu: config.security.tofu,
+            reputation: config.reputation.clone(),
+            connected_since: HashMap::new(),
+            relay_circuits,
+            relay_listeners: HashMap::new(),
+            configured_external_addrs: config.network.external_addrs.clone(),
+            flush_interval: tokio::time::interval(config.storage.flush_interval),
+            commands,
+            events,
+            pending_dials: HashMap::new(),
+            pending_sends: HashMap::new(),
+        };
+        node.reserve_relays();
+        Ok((node, handle))
+    }
+
+    /// Runs until `NodeHandle::shutdown` is called or every handle is dropped.
+    pub async fn run(mut self) -> Result<()> {
+        loop {
+            tokio::select! {
+                _ = self.flush_interval.tick() => {
+                    if let Err(e) = self.peer_store.flush() {
+                        tracing::warn!("Failed to flush peer store: {e:#}");
+                    }
+                    if let Err(e) = self.swarm.behaviour_mut().flush_kad_records() {
+                        tracing::warn!("Failed to flush Kademlia records: {e:#}");
+                    }
+                    if let Err(e) = self.swarm.behaviour_mut().refresh_gate() {
+                        tracing::warn!("Failed to refresh ban list: {e:#}");
+                    }
+                    self.reserve_relays();
+                },
+
+                _ = tokio::time::sleep_until(self.bootstrapper.next_attempt()) => {
+                    if let Some(event) = self.bootstrapper.poll(self.swarm.behaviour_mut()) {
+                        self.on_bootstrap_event(&event);
+                    }
+                },
+
+                command = self.commands.recv() => match command {
+                    Some(NodeCommand::Shutdown(reply)) => {
+                        tracing::info!("Shutting down");
+                        let _ = reply.send(self.flush());
+                        return Ok(());
+                    },
+                    Some(command) => self.handle_command(command),
+                    None => return self.flush(),
+                },
+
+                event = self.swarm.select_next_some() => self.handle_swarm_event(event),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.peer_store.flush()?;
+        self.swarm.behaviour_mut().flush_kad_records()
+    }
+
+    fn emit(&self, event: NodeEvent) {
+        // Fails only when nobody is subscribed.
+        let _ = self.events.send(event);
+    }
+
+    fn handle_command(&mut self, command: NodeCommand) {
+        match command {
+            NodeCommand::Info(reply) => {
+                let public_key: String = self.e2e_keys.public_key().as_bytes().iter().map(|byte| format!("{byte:02x}")).collect();
+                let _ = reply.send(Ok(NodeInfo {
+                    peer_id: *self.swarm.local_peer_id(),
+                    e2e_public_key: public_key,
+                    listen_addrs: self.swarm.listeners().cloned().collect(),
+                    external_addrs: self.swarm.external_addresses().cloned().collect(),
+                    reachability: self.swarm.behaviour().nat_status().into(),
+                }));
+            },
+            NodeCommand::Peers(reply) => {
+                let peers = self.peer_store.list_peers().into_iter().map(|(peer_id, info)| PeerSummary {
+                    peer_id: *peer_id,
+                    addresses: info.addresses.clone(),
+                    agent_version: info.agent_version.clone(),
+                    last_seen: info.last_seen,
+                    connected: self.swarm.is_connected(peer_id),
+                    verified: self.peer_store.is_verified(peer_id),
+                    score: self.peer_store.score(peer_id, &self.reputation),
+                }).collect();
+                let _ = reply.send(Ok(peers));
+            },
+            NodeCommand::AddPeerAddress { peer, address, reply } => {
+                self.swarm.add_peer_address(peer, address);
+                let _ = reply.send(Ok(()));
+            },
+            NodeCommand::Dial { address, reply } => {
+                let opts = DialOpts::unknown_peer_id().address(address).build();
+                let connection_id = opts.connection_id();
+                match self.swarm.dial(opts) {
+                    Ok(()) => {
+                        self.pending_dials.insert(connection_id, reply);
+                    },
+                    Err(e) => {
+                        let _ = reply.send(Err(e.into()));
+                    },
+                }
+            },
+            NodeCommand::Send { peer, text, reply } => {
+                let message = ChatMessage::new(*self.swarm.local_peer_id(), text);
+                match message.seal(&self.e2e_keys, &peer) {
+                    Ok(request) => {
+                        if !self.swarm.is_connected(&peer) {
+                            self.dial_known_peer(&peer);
+                        }
+                        let request_id = self.swarm.behaviour_mut().send_chat(&peer, request);
+                        tracing::info!("[CHAT] Sending message {} to {} (request {})", message.id, peer, request_id);
+                        self.pending_sends.insert(request_id, reply);
+                    },
+                    Err(e) => {
+                        let _ = reply.send(Err(anyhow!("Could not encrypt message for {peer}: {e}")));
+                    },
+                }
+            },
+            NodeCommand::JoinRoom { room, reply } => {
+                let result = self.swarm.behaviour_mut().join_room(&room).map_err(|e| anyhow!("Could not join {room}: {e:?}"));
+                let _ = reply.send(result);
+            },
+            NodeCommand::LeaveRoom { room, reply } => {
+                let _ = reply.send(Ok(self.swarm.behaviour_mut().leave_room(&room)));
+            },
+            NodeCommand::Publish { room, text, reply } => {
+                let message = RoomMessage::new(*self.swarm.local_peer_id(), &room, text);
+                let result = match self.swarm.behaviour_mut().publish_room_message(&message) {
+                    Ok(_) => {
+                        tracing::info!("[ROOM] Published message {} to {}", message.id, room);
+                        Ok(message.id)
+                    },
+                    Err(e) => Err(anyhow!("Could not publish to {room}: {e}")),
+                };
+                let _ = reply.send(result);
+            },
+            NodeCommand::Rooms(reply) => {
+                let behaviour = self.swarm.behaviour();
+                let rooms = behaviour.joined_rooms().into_iter().map(|room| RoomSummary {
+                    members: behaviour.room_members(&room),
+                    room,
+                }).collect();
+                let _ = reply.send(Ok(rooms));
+            },
+            NodeCommand::SafetyNumber { peer, reply } => {
+                let number = safety_number_for(self.swarm.local_peer_id(), &peer)
+                    .ok_or_else(|| anyhow!("{peer} does not use an ed25519 key"));
+                let _ = reply.send(number);
+            },
+            NodeCommand::Verify { peer, verified, reply } => {
+                let result = if verified {
+                    self.peer_store.mark_verified(&peer)
+                } else {
+                    self.peer_store.clear_verified(&peer);
+                    Ok(())
+                };
+                let _ = reply.send(result);
+            },
+            NodeCommand::Unpin { address, reply } => {
+                let _ = reply.send(Ok(self.peer_store.unpin_address(&address)));
+            },
+            NodeCommand::Prune { max_age, reply } => {
+                let _ = reply.send(Ok(self.peer_store.prune_stale(max_age)));
+            },
+            NodeCommand::Shutdown(_) => unreachable!("handled by the run loop"),
+        }
+    }
+
+    fn handle_swarm_event(&mut self, event: SwarmEvent<DissonanceEvent>) {
+        match event {
+            SwarmEvent::Behaviour(DissonanceEvent::Kademlia(event)) => self.on_kademlia_event(event),
+            SwarmEvent::Behaviour(DissonanceEvent::Autonat(event)) => self.on_autonat_event(event),
+            SwarmEvent::Behaviour(DissonanceEvent::Identify(event)) => self.on_identify_event(event),
+            SwarmEvent::Behaviour(DissonanceEvent::Mdns(event)) => self.on_mdns_event(event),
+            SwarmEvent::Behaviour(DissonanceEvent::Chat(event)) => self.on_chat_event(event),
+            SwarmEvent::Behaviour(DissonanceEvent::Room(event)) => self.on_room_event(event),
+            SwarmEvent::Behaviour(DissonanceEvent::Succession(event)) => self.on_succession_event(event),
+            SwarmEvent::Behaviour(DissonanceEvent::RelayClient(event)) => self.on_relay_client_event(event),
+            SwarmEvent::Behaviour(DissonanceEvent::RelayServer(event)) => on_relay_server_event(event),
+            SwarmEvent::Behaviour(DissonanceEvent::Dcutr(event)) => self.on_dcutr_event(event),
+
+            SwarmEvent::NewListenAddr { address, .. } => {
+                tracing::info!("Local node is listening on {address}");
+                tracing::info!("Full address: {address}/p2p/{}", self.swarm.local_peer_id());
+                self.emit(NodeEvent::ListeningOn { address });
+            },
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                tracing::info!("[AUTONAT] Reachable at {address}");
+                self.emit(NodeEvent::ExternalAddressConfirmed { address });
+            },
+            SwarmEvent::ExternalAddrExpired { address } => {
+                tracing::warn!("[AUTONAT] No longer reachable at {address}");
+                self.emit(NodeEvent::ExternalAddressExpired { address });
+            },
+            SwarmEvent::ListenerClosed { listener_id, reason, .. } => {
+                if let Some(circuit) = self.relay_listeners.remove(&listener_id) {
+                    // Retried on the next flush tick.
+                    tracing::warn!("[RELAY] Lost reservation on {circuit}: {reason:?}");
+                }
+            },
+            SwarmEvent::IncomingConnection { local_addr, send_back_addr, .. } => {
+                tracing::info!("Incoming connection from {send_back_addr} on {local_addr}");
+            },
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, num_established, .. } => {
+                tracing::info!("Connected to peer: {peer_id} via {endpoint:?}");
+                if self.peer_store.standing(&peer_id, &self.reputation) == Standing::Disconnected {
+                    tracing::warn!("[REPUTATION] Closing connection to {peer_id}: reputation too low");
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                    if let Some(reply) = self.pending_dials.remove(&connection_id) {
+                        let _ = reply.send(Err(anyhow!("{peer_id} is disconnected for its low reputation")));
+                    }
+                    return;
+                }
+                if endpoint.is_dialer() && !self.check_address_pin(endpoint.get_remote_address(), &peer_id) {
+                    if let Some(reply) = self.pending_dials.remove(&connection_id) {
+                        let _ = reply.send(Err(anyhow!("{} is pinned to a different peer than {peer_id}", endpoint.get_remote_address())));
+                    }
+                    return;
+                }
+                if endpoint.is_dialer() {
+                    // Addresses we dialed successfully are worth remembering; a listener only sees ephemeral ports.
+                    self.peer_store.add_peer_address(&peer_id, endpoint.get_remote_address().clone());
+                }
+                if let Some(reply) = self.pending_dials.remove(&connection_id) {
+                    let _ = reply.send(Ok(peer_id));
+                }
+                if num_established.get() == 1 {
+                    self.connected_since.insert(peer_id, Instant::now());
+                    for certificate in self.successions.clone() {
+                        self.swarm.behaviour_mut().announce_succession(&peer_id, certificate);
+                    }
+                    self.emit(NodeEvent::PeerConnected { peer: peer_id, address: endpoint.get_remote_address().clone() });
+                }
+            },
+            SwarmEvent::OutgoingConnectionError { connection_id, peer_id, error } => {
+                // Being offline or unreachable is not misbehaviour; only count failed handshakes.
+                if let Some(peer) = peer_id && is_handshake_failure(&error) {
+                    self.record_signal(&peer, Signal::FailedHandshake);
+                }
+                if let Some(reply) = self.pending_dials.remove(&connection_id) {
+                    let _ = reply.send(Err(error.into()));
+                }
+            },
+            SwarmEvent::ConnectionClosed { peer_id, cause, num_established, .. } => {
+                tracing::info!("Connection to {peer_id} closed: {cause:?}");
+                if num_established == 0 {
+                    if let Some(since) = self.connected_since.remove(&peer_id) {
+                        self.record_signal(&peer_id, Signal::Uptime(since.elapsed()));
+                    }
+                    self.emit(NodeEvent::PeerDisconnected { peer: peer_id });
+                }
+            },
+            _ => {
+                //Handle silently
+            }
+        }
+    }
+
+    fn on_kademlia_event(&mut self, event: KademliaEvent) {
+        match event {
+            KademliaEvent::RoutingUpdated{peer,addresses,..}=>{
+                // FUTURE:
+                // - If `is_new_peer`, persist this peer in your local disk-backed store DONE
+                //   so the node remembers it after restart (important for bootstrap performance). DONE
+                // - Use `addresses` to update your local peer-address book (with timestamp). DONE
+                // - Could check peer reputation/behavior and decide whether to keep it in the routing table. DONE
+                // - If this peer is a new one, trigger Identify protocol to fetch full info. TODO
+                // - Use peer reputation score to decide whether to keep them. DONE
+                let peer_info = self.peer_store.get_or_create(&peer);
+                peer_info.addresses = addresses.into_vec();
+                peer_info.last_seen = SystemTime::now();
+                if self.peer_store.standing(&peer, &self.reputation) >= Standing::Evicted {
+                    self.swarm.behaviour_mut().remove_kademlia_peer(&peer);
+                    tracing::warn!("[REPUTATION] Kept {} out of the routing table", peer);
+                    return;
+                }
+                tracing::debug!("[KAD] Routing table updated with the following peer details: {}",peer);
+            },
+            KademliaEvent::InboundRequest{request}=>{
+                tracing::debug!("[KAD] Inbound request on DHT");
+                // FUTURE:
+                // - Handle `GetRecord` or `PutRecord` requests. DONE for `PutRecord`
+                // - You might filter what keys you allow others to store (anti-spam / DoS protection). DONE by reputation
+                match request {
+                    InboundRequest::PutRecord { source, record: Some(record), .. } => {
+                        if self.peer_store.standing(&source, &self.reputation) >= Standing::Suspect {
+                            tracing::warn!("[REPUTATION] Refused record from {}", source);
+                        } else if let Err(e) = self.swarm.behaviour_mut().store_inbound_record(record) {
+                            tracing::warn!("[KAD] Could not store record from {}: {}", source, e);
+                        }
+                    },
+                    InboundRequest::AddProvider { record: Some(provider) } => {
+                        let source = provider.provider;
+                        if self.peer_store.standing(&source, &self.reputation) >= Standing::Suspect {
+                            tracing::warn!("[REPUTATION] Refused provider record from {}", source);
+                        } else if let Err(e) = self.swarm.behaviour_mut().store_inbound_provider(provider) {
+                            tracing::warn!("[KAD] Could not store provider record from {}: {}", source, e);
+                        }
+                    },
+                    _ => {},
+                }
+                // - Optionally encrypt data stored on DHT if privacy is a concern (e.g. store ciphertext only).
+                // - Consider rate limiting or proof-of-work for writes to mitigate Sybil spam.
+            },
+            KademliaEvent::OutboundQueryProgressed{id,result,step,..}=>{
+                tracing::debug!("[KAD] Query {} progressed {:?}",id,result);
+                if let QueryResult::Bootstrap(result) = &result
+                    && let Some(event) = self.bootstrapper.on_query_progressed(id, result, &step) {
+                    self.on_bootstrap_event(&event);
+                    // Records we hosted before a restart can only be re-announced once we have a routing table.
+                    if matches!(event, BootstrapEvent::Succeeded { .. }) && !self.records_republished {
+                        self.records_republished = true;
+                        let started = self.swarm.behaviour_mut().republish_kad_records();
+                        tracing::info!("[KAD] Republishing {} stored records", started);
+                    }
+                }
+                if let QueryResult::GetRecord(Ok(GetRecordOk::FoundRecord(found))) = &result
+                    && self.succession_queries.remove(&id)
+                    && found.peer.is_none_or(|peer| self.peer_store.standing(&peer, &self.reputation) < Standing::Suspect) {
+                    match serde_json::from_slice::<SuccessionCertificate>(&found.record.value) {
+                        Ok(certificate) => {
+                            // The record key only says who it claims to succeed; the certificate has to agree.
+                            let key = certificate.previous().map(|previous| SuccessionCertificate::record_key(&previous));
+                            if key.is_ok_and(|key| key == found.record.key) {
+                                self.on_succession_certificate(certificate);
+                            }
+                        },
+                        Err(e) => tracing::warn!("[SUCCESSION] Ignoring malformed succession record: {e}"),
+                    }
+                }
+                // FUTURE:
+                // - Use `result` to know whether a peer lookup or record lookup was successful.
+                // - If this was a bootstrap query, check `stats` to decide whether to launch more queries. DONE
+                // - If looking up a peer for message delivery, this is where you connect/send message.
+                // - Optionally log query performance to tune parallelism or timeouts.
+            },
+            KademliaEvent::UnroutablePeer { peer } => {
+                tracing::debug!("[KAD] Unroutable peer detected: {}", peer);
+                // FUTURE: Could log metrics or attempt to refresh this peer's record.
+                // Maybe schedule a re-bootstrap or remove it from the routing table if repeated.
+            },
+            KademliaEvent::RoutablePeer { peer, address } => {
+                tracing::debug!("[KAD] Routable peer {} detected with address {:?}", peer, address);
+                // FUTURE: This is a good place to store peer information in a local peer store.DONE
+                // Can also trigger any queued messages for this peer since it's reachable now.
+                let peer_info = self.peer_store.get_or_create(&peer);
+                peer_info.add_address(address);
+                tracing::debug!("[KAD] Routable peer {} added", peer);
+            },
+            KademliaEvent::PendingRoutablePeer { peer, address } => {
+                tracing::debug!("[KAD] Pending routable peer {} with address {:?}", peer, address);
+                // FUTURE: This is when the peer is found but not yet fully confirmed.
+                // You could attempt a direct connection here, or verify Noise handshake before trusting it.
+                let peer_info = self.peer_store.get_or_create(&peer);
+                peer_info.add_address(address);
+                tracing::debug!("[KAD] Routable peer {} added", peer);
+            },
+            KademliaEvent::ModeChanged { new_mode } => {
+                tracing::info!("[KAD] mode changed to {:?}", new_mode);
+                // Follows the confirmed external addresses, see `on_autonat_event`. DONE
+                // FUTURE:
+                // If switched to client mode (e.g. behind NAT), maybe trigger bootstrap more often.
+                self.emit(NodeEvent::DhtModeChanged { server: new_mode == KademliaMode::Server });
+            },
+        }
+    }
+
+    fn on_identify_event(&mut self, event: IdentifyEvent) {
+        match event{
+            IdentifyEvent::Received { connection_id, peer_id, info } => {
+                // FUTURE:
+                // - Store peer's `info` (agent version, supported protocols, listen addresses) DONE
+                //   in your local peer database to help future connections. DONE
+                // - Verify the info (e.g., supported protocols match what you expect). TODO
+                // - Could enforce minimum supported protocol versions here (disconnect otherwise). DONE
+                // - Might use peer's public key for TOFU (Trust On First Use) logic. DONE
+                tracing::debug!("[IDENTIFY] Received identity info from peer: {} on connection {:?}", peer_id, connection_id);
+                self.on_identify_info(peer_id, info);
+            },
+            IdentifyEvent::Sent { connection_id, peer_id } => {
+                tracing::debug!("[IDENTIFY] Sent our identity info to peer: {} on connection {:?}", peer_id, connection_id);
+                // FUTURE:
+                // - Log which peers you have identified to — could track handshake success rate.
+                // - This is useful to know when you can safely send encrypted messages to this peer.
+            },
+            IdentifyEvent::Pushed { connection_id, peer_id, .. } => {
+                // `info` is our own identity, pushed to the peer; pushes we receive arrive as `Received`.
+                tracing::debug!("[IDENTIFY] Pushed our identity info to peer: {} on connection {:?}", peer_id, connection_id);
+            },
+            IdentifyEvent::Error { connection_id, peer_id, error } => {
+                tracing::warn!("[IDENTIFY] Error with peer {} on connection {:?}: {:?}", peer_id, connection_id, error);
+                self.record_signal(&peer_id, Signal::FailedHandshake);
+                // FUTURE:
+                // - Log or count errors for peer reputation system (e.g., disconnect on repeated failures). DONE
+                // - You may want to retry identification after a delay.
+                // - Could trigger peer ban if error indicates malicious behaviour.
+            },
+        }
+    }
+
+    /// Dials `peer` at its remembered addresses, QUIC first, followed by any the behaviours know.
+    fn dial_known_peer(&mut self, peer: &PeerId) {
+        let mut addresses = self.peer_store.get(peer).map(|info| info.addresses.clone()).unwrap_or_default();
+        prefer_quic(&mut addresses);
+        // A relayed connection is a last resort: it is slow and the relay caps its lifetime and volume.
+        addresses.sort_by_key(is_relayed);
+        let opts = DialOpts::peer_id(*peer).addresses(addresses).extend_addresses_through_behaviour().build();
+        if let Err(e) = self.swarm.dial(opts) {
+            tracing::warn!("Could not dial {peer}: {e}");
+        }
+    }
+
+    fn on_bootstrap_event(&mut self, event: &BootstrapEvent) {
+        match event {
+            BootstrapEvent::Started { query_id } => tracing::info!("[BOOTSTRAP] Started bootstrap query {}", query_id),
+            BootstrapEvent::Succeeded { query_id, next_in } => {
+                tracing::info!("[BOOTSTRAP] Bootstrap query {} succeeded, next run in {:?}", query_id, next_in);
+                self.emit(NodeEvent::BootstrapSucceeded { next_in: *next_in });
+            },
+            BootstrapEvent::Failed { reason, failures, retry_in } => {
+                tracing::warn!("[BOOTSTRAP] Bootstrap failed ({} in a row): {}, retrying in {:?}", failures, reason, retry_in);
+                self.emit(NodeEvent::BootstrapFailed { reason: reason.clone(), failures: *failures, retry_in: *retry_in });
+            },
+        }
+    }
+
+    /// Listens through every configured relay that we do not hold a reservation on yet.
+    fn reserve_relays(&mut self) {
+        for circuit in &self.relay_circuits {
+            if self.relay_listeners.values().any(|listening| listening == circuit) {
+                continue;
+            }
+            match self.swarm.listen_on(circuit.clone()) {
+                Ok(listener_id) => {
+                    self.relay_listeners.insert(listener_id, circuit.clone());
+                },
+                Err(e) => tracing::warn!("[RELAY] Could not reserve a slot on {circuit}: {e}"),
+            }
+        }
+    }
+
+    fn on_relay_client_event(&mut self, event: relay::client::Event) {
+        match event {
+            relay::client::Event::ReservationReqAccepted { relay_peer_id, renewal, .. } => {
+                tracing::info!("[RELAY] Reservation on {} {}", relay_peer_id, if renewal { "renewed" } else { "accepted" });
+                if !renewal {
+                    self.emit(NodeEvent::RelayReserved { relay: relay_peer_id });
+                }
+            },
+            relay::client::Event::OutboundCircuitEstablished { relay_peer_id, .. } => {
+                tracing::info!("[RELAY] Opened a circuit through {}", relay_peer_id);
+            },
+            relay::client::Event::InboundCircuitEstablished { src_peer_id, .. } => {
+                tracing::info!("[RELAY] {} reached us through a relay", src_peer_id);
+            },
+        }
+    }
+
+    fn on_autonat_event(&mut self, event: autonat::Event) {
+        match event {
+            autonat::Event::StatusChanged { old, new } => {
+                tracing::info!("[AUTONAT] Reachability changed from {:?} to {:?}", old, new);
+                if new == autonat::NatStatus::Private {
+                    // AutoNAT confirms addresses but never withdraws them. Do it here so Kademlia
+                    // drops back to client mode instead of advertising an address nobody can dial.
+                    let unreachable: Vec<Multiaddr> = self.swarm.external_addresses()
+                        .filter(|address| !self.configured_external_addrs.contains(address))
+                        .cloned()
+                        .collect();
+                    for address in unreachable {
+                        self.swarm.remove_external_address(&address);
+                    }
+                }
+                self.emit(NodeEvent::ReachabilityChanged { reachability: Reachability::from(new) });
+            },
+            other => tracing::debug!("AutoNAT probe: {other:?}"),
+        }
+    }
+
+    fn on_dcutr_event(&mut self, event: dcutr::Event) {
+        let peer = event.remote_peer_id;
+        match event.result {
+            Ok(connection_id) => {
+                tracing::info!("[DCUTR] Upgraded relayed connection to {} to a direct one ({:?})", peer, connection_id);
+                self.emit(NodeEvent::DirectConnectionUpgraded { peer });
+            },
+            Err(e) => {
+                // Nothing to undo: the relayed connection stays up and keeps carrying traffic.
+                tracing::warn!("[DCUTR] Could not reach {} directly, staying on the relay: {}", peer, e);
+                self.emit(NodeEvent::DirectConnectionFailed { peer, error: e.to_string() });
+            },
+        }
+    }
+
+    /// Checks a peer's key and version, then stores what it told us. Incompatible peers are disconnected.
+    fn on_identify_info(&mut self, peer_id: PeerId, info: IdentifyInfo) {
+        if !self.check_listen_addr_pins(&peer_id, &info.listen_addrs) {
+            return;
+        }
+        match check_compatibility(&info.protocol_version, &info.agent_version) {
+            Compatibility::Compatible(_) | Compatibility::Foreign => {},
+            Compatibility::Incompatible(reason) => {
+                tracing::warn!("[IDENTIFY] Disconnecting incompatible peer {} ({}): {}", peer_id, info.agent_version, reason);
+                self.emit(NodeEvent::PeerIncompatible { peer: peer_id, agent_version: info.agent_version, reason });
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+                return;
+            },
+        }
+        // Merge rather than replace so trust, verification, pins and dialed addresses survive a re-identify.
+        for address in info.listen_addrs.clone() {
+            self.peer_store.add_peer_address(&peer_id, address);
+        }
+        self.peer_store.add_peer_identity(&peer_id, info);
+    }
+
+    /// Adds `signal` to the peer's reputation and applies the standing that results.
+    fn record_signal(&mut self, peer: &PeerId, signal: Signal) {
+        let before = self.peer_store.standing(peer, &self.reputation);
+        let standing = self.peer_store.record_signal(peer, signal, &self.reputation);
+        if standing == before {
+            return;
+        }
+        let score = self.peer_store.score(peer, &self.reputation);
+        tracing::info!("[REPUTATION] {} is now {:?} (score {:.1})", peer, standing, score);
+        self.emit(NodeEvent::StandingChanged { peer: *peer, standing, score });
+        if standing >= Standing::Evicted {
+            self.swarm.behaviour_mut().remove_kademlia_peer(peer);
+        }
+        if standing == Standing::Disconnected {
+            let _ = self.swarm.disconnect_peer_id(*peer);
+        }
+    }
+
+    /// Checks that a dialed address is still answered by the peer that answered it first.
+    /// Returns whether the connection may stay open.
+    fn check_address_pin(&mut self, address: &Multiaddr, peer_id: &PeerId) -> bool {
+        let PinCheck::Mismatched(pinned) = self.peer_store.check_address_pin(address, peer_id) else {
+            return true;
+        };
+        self.on_pin_mismatch(address, pinned, peer_id)
+    }
+
+    /// Checks the addresses a peer says it listens on against the peers that first answered there.
+    /// These are only claims, so unlike dialed addresses they never create pins.
+    /// Returns whether the connection may stay open.
+    fn check_listen_addr_pins(&mut self, peer_id: &PeerId, listen_addrs: &[Multiaddr]) -> bool {
+        for address in listen_addrs {
+            if let Some(pinned) = self.peer_store.pinned_peer(address)
+                && pinned != *peer_id
+                && !self.on_pin_mismatch(address, pinned, peer_id) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Reports `peer_id` turning up at an address pinned to `pinned` and, in block mode, disconnects it.
+    fn on_pin_mismatch(&mut self, address: &Multiaddr, pinned: PeerId, peer_id: &PeerId) -> bool {
+        let blocked = self.tofu == TofuMode::Block;
+        tracing::warn!("[TOFU] {} turned up at {}, which is pinned to {}{}", peer_id, address, pinned, if blocked { "; disconnecting" } else { "" });
+        self.emit(NodeEvent::AddressMismatch { address: address.clone(), pinned, peer: *peer_id, blocked });
+        if blocked {
+            let _ = self.swarm.disconnect_peer_id(*peer_id);
+        }
+        !blocked
+    }
+
+    fn on_mdns_event(&mut self, event: MdnsEvent) {
+        match event {
+            MdnsEvent::Discovered(peers) => {
+                for (peer, addr) in peers {
+                    tracing::info!("[MDNS] Discovered peer {} at {:?}", peer, addr);
+                    // FUTURE:
+                    // - Add discovered peer to kademlia for routing table updates
+                    self.swarm.behaviour_mut().add_kademlia_address(&peer, addr.clone());
+                    self.emit(NodeEvent::PeerDiscovered { peer, address: addr });
+                }
+            },
+            MdnsEvent::Expired(peers) => {
+                for (peer, addr) in peers {
+                    tracing::info!("[MDNS] Peer expired: {} at {:?}", peer, addr);
+                    // FUTURE: Optionally remove peer from routing table if no longer reachable
+                }
+            }
+        }
+    }
+
+    fn on_chat_event(&mut self, event: ChatEvent) {
+        match event {
+            request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. }, .. } => {
+                // FUTURE:
+                // - Persist the message so it survives a restart and can be shown in history.
+                // - Count rejected messages towards the sender's reputation. DONE
+                let ack = match request.open(&self.e2e_keys) {
+                    Ok(message) if message.is_from(&peer) => {
+                        tracing::info!("[CHAT] {} ({}): {}", peer, message.id, message.body);
+                        let ack = ChatAck::delivered(&message.id);
+                        self.emit(NodeEvent::ChatMessage { peer, id: message.id, timestamp: message.timestamp, body: message.body });
+                        ack
+                    },
+                    Ok(message) => {
+                        tracing::warn!("[CHAT] Rejected message {} from {} claiming to be {}", message.id, peer, message.sender);
+                        self.record_signal(&peer, Signal::ProtocolError);
+                        ChatAck::rejected(&message.id, "sender does not match connection")
+                    },
+                    Err(e) => {
+                        tracing::warn!("[CHAT] Could not open message {} from {}: {}", request.id, peer, e);
+                        self.record_signal(&peer, Signal::ProtocolError);
+                        ChatAck::rejected(&request.id, e.to_string())
+                    },
+                };
+                if self.swarm.behaviour_mut().acknowledge_chat(channel, ack).is_err() {
+                    tracing::warn!("[CHAT] Could not acknowledge message {} from {}: channel closed", request.id, peer);
+                }
+            },
+            request_response::Event::Message { peer, message: request_response::Message::Response { request_id, response }, .. } => {
+                match &response.status {
+                    DeliveryStatus::Delivered => {
+                        tracing::info!("[CHAT] Message {} delivered to {}", response.id, peer);
+                        self.record_signal(&peer, Signal::Delivered);
+                    },
+                    DeliveryStatus::Rejected(reason) => tracing::warn!("[CHAT] Message {} rejected by {}: {}", response.id, peer, reason),
+                }
+                self.emit(NodeEvent::ChatAck { peer, id: response.id.clone(), status: response.status.clone() });
+                if let Some(reply) = self.pending_sends.remove(&request_id) {
+                    let _ = reply.send(Ok(response));
+                }
+            },
+            request_response::Event::OutboundFailure { peer, request_id, error, .. } => {
+                tracing::warn!("[CHAT] Failed to deliver request {} to {}: {}", request_id, peer, error);
+                // FUTURE: queue the message and retry once the peer is routable again.
+                self.emit(NodeEvent::ChatFailed { peer, error: error.to_string() });
+                // The peer may have rotated its key and announced its successor while we were away.
+                let query = self.swarm.behaviour_mut().find_succession(&peer);
+                self.succession_queries.insert(query);
+                if let Some(reply) = self.pending_sends.remove(&request_id) {
+                    let _ = reply.send(Err(anyhow!("Could not deliver message to {peer}: {error}")));
+                }
+            },
+            request_response::Event::InboundFailure { peer, request_id, error, .. } => {
+                tracing::warn!("[CHAT] Inbound request {} from {} failed: {}", request_id, peer, error);
+            },
+            request_response::Event::ResponseSent { .. } => {},
+        }
+    }
+
+    fn on_room_event(&mut self, event: RoomEvent) {
+        match event {
+            RoomEvent::Joined { room } => tracing::info!("[ROOM] Joined {}", room),
+            RoomEvent::Left { room } => tracing::info!("[ROOM] Left {}", room),
+            RoomEvent::MemberJoined { room, peer } => {
+                tracing::info!("[ROOM] {} joined {}", peer, room);
+                self.emit(NodeEvent::RoomMemberJoined { room, peer });
+            },
+            RoomEvent::MemberLeft { room, peer } => {
+                tracing::info!("[ROOM] {} left {}", peer, room);
+                self.emit(NodeEvent::RoomMemberLeft { room, peer });
+            },
+            RoomEvent::Message { message, .. } => {
+                tracing::info!("[ROOM] #{} {} ({}): {}", message.room, message.sender, message.id, message.body);
+                self.emit(NodeEvent::RoomMessage { room: message.room, sender: message.sender, id: message.id, timestamp: message.timestamp, body: message.body });
+            },
+            RoomEvent::Rejected { room, source, propagation_source, error } => {
+                tracing::warn!("[ROOM] Rejected message in {} forwarded by {}: {}", room, propagation_source, error);
+                // Gossipsub already scores the forwarder; our reputation is for whoever signed it.
+                if let Some(source) = source {
+                    self.record_signal(&source, Signal::Spam);
+                }
+            },
+            RoomEvent::Forged { room, sender, id, error } => {
+                tracing::warn!("[ROOM] Message {} from {} in {} could not be opened: {}", id, sender, room, error);
+            },
+            RoomEvent::Unreadable { room, sender, id } => {
+                tracing::info!("[ROOM] Message {} from {} in {} was not sealed for us", id, sender, room);
+            },
+            RoomEvent::Unsupported { peer } => tracing::info!("[ROOM] Peer {} does not support group chat", peer),
+        }
+    }
+
+    fn on_succession_event(&mut self, event: SuccessionEvent) {
+        match event {
+            request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. }, .. } => {
+                let accepted = self.on_succession_certificate(request);
+                if !accepted {
+                    self.record_signal(&peer, Signal::ProtocolError);
+                }
+                if self.swarm.behaviour_mut().acknowledge_succession(channel, SuccessionAck { accepted }).is_err() {
+                    tracing::warn!("[SUCCESSION] Could not acknowledge certificate from {}: channel closed", peer);
+                }
+            },
+            request_response::Event::Message { peer, message: request_response::Message::Response { response, .. }, .. } => {
+                if response.accepted {
+                    tracing::info!("[SUCCESSION] {} accepted our succession certificate", peer);
+                }
+            },
+            request_response::Event::OutboundFailure { .. }
+            | request_response::Event::InboundFailure { .. }
+            | request_response::Event::ResponseSent { .. } => {},
+        }
+    }
+
+    /// Verifies `certificate` and moves the stored contact over to its successor.
+    /// Returns whether the certificate was valid.
+    fn on_succession_certificate(&mut self, certificate: SuccessionCertificate) -> bool {
+        let (previous, successor) = match certificate.verify().and_then(|()| Ok((certificate.previous()?, certificate.successor()?))) {
+            Ok(peers) => peers,
+            Err(e) => {
+                tracing::warn!("[SUCCESSION] Rejected succession certificate: {e:#}");
+                return false;
+            },
+        };
+        if let Some(succession) = self.peer_store.apply_succession(&previous, &successor) {
+            tracing::info!("[SUCCESSION] {} rotated its identity to {}", previous, successor);
+            self.emit(NodeEvent::PeerRotated { previous, successor });
+            if succession.was_verified {
+                tracing::info!("[VERIFY] Verified contact {} now uses a new key as {}; compare safety numbers again", previous, successor);
+                self.emit(NodeEvent::ContactKeyChanged { previous, successor });
+            }
+        }
+        true
+    }
+}
+
+/// Whether a dial reached the peer but the security or protocol negotiation failed, as opposed to
+/// the peer being offline or unreachable.
+fn is_handshake_failure(error: &DialError) -> bool {
+    match error {
+        DialError::WrongPeerId { .. } => true,
+        DialError::Transport(errors) => errors.iter().any(|(_, error)| match error {
+            TransportError::Other(error) => is_negotiation_error(error),
+            TransportError::MultiaddrNotSupported(_) => false,
+        }),
+        _ => false,
+    }
+}
+
+/// Whether `error` or anything it wraps is a failed noise handshake or protocol negotiation.
+fn is_negotiation_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(error) = error.downcast_ref::<noise::Error>() {
+            return !matches!(error, noise::Error::Io(_));
+        }
+        if let Some(error) = error.downcast_ref::<NegotiationError>() {
+            return !matches!(error, NegotiationError::ProtocolError(ProtocolError::IoError(_)));
+        }
+        // `io::Error::source` skips the error it wraps, so look inside explicitly.
+        current = match error.downcast_ref::<std::io::Error>() {
+            Some(error) => error.get_ref().map(|inner| inner as &(dyn std::error::Error + 'static)),
+            None => error.source(),
+        };
+    }
+    false
+}
+
+/// Our own succession chain, if it ends at the identity this node runs as.
+fn load_successions(config: &Config, identity: &NodeIdentity) -> Vec<SuccessionCertificate> {
+    let chain = match config.data_dir().and_then(|data_dir| SuccessionCertificate::load_chain(&SuccessionCertificate::path(&data_dir))) {
+        Ok(chain) => chain,
+        Err(e) => {
+            tracing::warn!("[SUCCESSION] Could not load succession certificates: {e:#}");
+            return Vec::new();
+        },
+    };
+    match chain.last().map(|certificate| certificate.successor_key) {
+        Some(key) if key == identity.pub_key_bytes() => chain,
+        // An ephemeral or imported identity is not the one these certificates vouch for.
+        _ => Vec::new(),
+    }
+}
+
+fn on_relay_server_event(event: relay::Event) {
+    match event {
+        relay::Event::ReservationReqAccepted { src_peer_id, renewed } => {
+            tracing::info!("[RELAY] {} reservation for {}", if renewed { "Renewed" } else { "Accepted" }, src_peer_id);
+        },
+        relay::Event::ReservationTimedOut { src_peer_id } => tracing::info!("[RELAY] Reservation for {} expired", src_peer_id),
+        relay::Event::CircuitReqAccepted { src_peer_id, dst_peer_id } => {
+            tracing::info!("[RELAY] Relaying {} -> {}", src_peer_id, dst_peer_id);
+        },
+        relay::Event::CircuitClosed { src_peer_id, dst_peer_id, error } => {
+            tracing::info!("[RELAY] Circuit {} -> {} closed: {:?}", src_peer_id, dst_peer_id, error);
+        },
+        other => tracing::debug!("Relay server event: {other:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn test_config() -> Config {
+        let mut config = Config::ephemeral();
+        config.network.listen_addrs = vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()];
+        config.mdns.enabled = false;
+        config
+    }
+
+    async fn listen_addr(node: &NodeHandle) -> Multiaddr {
+        let mut events = node.subscribe();
+        if let Some(address) = node.info().await.unwrap().listen_addrs.pop() {
+            return address;
+        }
+        loop {
+            if let NodeEvent::ListeningOn { address } = events.recv().await.unwrap() {
+                return address;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_failure_reaches_subscribers() {
+        // With no bootstrap peers and nothing remembered, the first attempt fails straight away.
+        let node = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut events = node.subscribe();
+        let event = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let event @ NodeEvent::BootstrapFailed { .. } = events.recv().await.unwrap() {
+                    return event;
+                }
+            }
+        }).await.expect("No bootstrap failure was reported");
+        assert!(matches!(event, NodeEvent::BootstrapFailed { failures: 1, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_handles_drive_two_nodes() {
+        let alice = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let bob = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut alice_events = alice.subscribe();
+
+        let exchange = async {
+            let connected = bob.dial(listen_addr(&alice).await).await.unwrap();
+            assert_eq!(connected, alice.peer_id());
+
+            let ack = bob.send(alice.peer_id(), "hello via handle").await.unwrap();
+            assert_eq!(ack.status, DeliveryStatus::Delivered);
+            loop {
+                if let NodeEvent::ChatMessage { peer, body, .. } = alice_events.recv().await.unwrap() {
+                    assert_eq!(peer, bob.peer_id());
+                    assert_eq!(body, "hello via handle");
+                    break;
+                }
+            }
+
+            let peers = bob.peers().await.unwrap();
+            assert!(peers.iter().any(|peer| peer.peer_id == alice.peer_id() && peer.connected));
+
+            // Identify runs alongside; another copy of this node is compatible and gets remembered.
+            let agent = crate::network::behaviours::identify::agent_version();
+            while !bob.peers().await.unwrap().iter().any(|peer| peer.peer_id == alice.peer_id() && peer.agent_version.as_ref() == Some(&agent)) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(20), exchange).await.expect("Exchange timed out");
+
+        alice.shutdown().await.unwrap();
+        assert!(alice.info().await.is_err(), "A stopped node should not answer");
+    }
+
+    #[tokio::test]
+    async fn test_rotated_peer_is_followed() {
+        let bob = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let bob_addr = listen_addr(&bob).await;
+        let mut bob_events = bob.subscribe();
+
+        let old_identity = NodeIdentity::generate_ephemeral().unwrap();
+        let old_alice = Node::spawn(test_config(), old_identity.clone()).unwrap();
+        let exchange = async {
+            old_alice.dial(bob_addr.clone()).await.unwrap();
+            // Bob only remembers addresses it dialed itself, so introduce Alice the other way round too.
+            bob.dial(listen_addr(&old_alice).await).await.unwrap();
+            bob.set_verified(old_identity.peer_id(), true).await.unwrap();
+            old_alice.shutdown().await.unwrap();
+
+            let temp = tempfile::tempdir().unwrap();
+            let new_identity = NodeIdentity::generate_ephemeral().unwrap();
+            SuccessionCertificate::issue(&old_identity, &new_identity).append_to(&SuccessionCertificate::path(temp.path())).unwrap();
+            let mut config = test_config();
+            config.storage.data_dir = Some(temp.path().to_path_buf());
+            let new_alice = Node::spawn(config, new_identity).unwrap();
+            new_alice.dial(bob_addr).await.unwrap();
+
+            loop {
+                if let NodeEvent::PeerRotated { previous, successor } = bob_events.recv().await.unwrap() {
+                    assert_eq!(previous, old_identity.peer_id());
+                    assert_eq!(successor, new_alice.peer_id());
+                    break;
+                }
+            }
+            // Bob had verified the old key, so the new one gets flagged.
+            assert!(matches!(bob_events.recv().await.unwrap(), NodeEvent::ContactKeyChanged { .. }));
+            let peers = bob.peers().await.unwrap();
+            assert!(peers.iter().all(|peer| peer.peer_id != old_identity.peer_id()));
+            assert!(peers.iter().any(|peer| peer.peer_id == new_alice.peer_id() && !peer.verified));
+        };
+        tokio::time::timeout(Duration::from_secs(20), exchange).await.expect("Rotation was not picked up");
+    }
+
+    #[tokio::test]
+    async fn test_address_answered_by_another_peer_is_blocked() {
+        let mut config = test_config();
+        config.security.tofu = TofuMode::Block;
+        let dialer = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut dialer_events = dialer.subscribe();
+
+        let original = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let address = listen_addr(&original).await;
+        let exchange = async {
+            dialer.dial(address.clone()).await.unwrap();
+            original.shutdown().await.unwrap();
+
+            // Someone else takes over the same address.
+            let mut config = test_config();
+            config.network.listen_addrs = vec![address.clone()];
+            let impostor = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+            listen_addr(&impostor).await;
+            assert!(dialer.dial(address.clone()).await.is_err(), "A pinned address answered by another peer must not connect");
+
+            loop {
+                if let NodeEvent::AddressMismatch { address: pinned_address, pinned, peer, blocked } = dialer_events.recv().await.unwrap() {
+                    assert_eq!(pinned_address, address);
+                    assert_eq!(pinned, original.peer_id());
+                    assert_eq!(peer, impostor.peer_id());
+                    assert!(blocked);
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(20), exchange).await.expect("Mismatch was not reported");
+    }
+
+    #[tokio::test]
+    async fn test_peer_claiming_a_pinned_address_is_blocked() {
+        let mut config = test_config();
+        config.security.tofu = TofuMode::Block;
+        let node = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut events = node.subscribe();
+        let node_addr = listen_addr(&node).await;
+
+        let original = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let address = listen_addr(&original).await;
+        let exchange = async {
+            node.dial(address.clone()).await.unwrap();
+
+            // Another peer advertises the pinned address as its own over Identify.
+            let mut config = test_config();
+            config.network.external_addrs = vec![address.clone()];
+            let impostor = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+            impostor.dial(node_addr).await.unwrap();
+
+            loop {
+                if let NodeEvent::AddressMismatch { address: pinned_address, pinned, peer, blocked } = events.recv().await.unwrap() {
+                    assert_eq!(pinned_address, address);
+                    assert_eq!(pinned, original.peer_id());
+                    assert_eq!(peer, impostor.peer_id());
+                    assert!(blocked);
+                    break;
+                }
+            }
+            loop {
+                if let NodeEvent::PeerDisconnected { peer } = events.recv().await.unwrap() {
+                    assert_eq!(peer, impostor.peer_id());
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(20), exchange).await.expect("Claimed address was not checked against its pin");
+    }
+
+    #[tokio::test]
+    async fn test_low_reputation_peers_are_disconnected() {
+        let mut config = test_config();
+        // Every peer starts at zero, which this threshold treats as too low.
+        config.reputation.disconnect_below = 1.0;
+        let picky = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let other = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+
+        let address = tokio::time::timeout(Duration::from_secs(20), listen_addr(&other)).await.unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(20), picky.dial(address)).await.unwrap();
+        assert!(result.is_err(), "A peer below the disconnect threshold must not stay connected");
+    }
+
+    #[tokio::test]
+    async fn test_banned_peers_and_networks_are_refused() {
+        use crate::network::behaviours::gate::{GateList, GateRule};
+
+        let banned = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let banned_addr = tokio::time::timeout(Duration::from_secs(20), listen_addr(&banned)).await.unwrap();
+
+        let temp = tempfile::tempdir().unwrap();
+        let mut list = GateList::open(&GateList::path(temp.path())).unwrap();
+        list.ban(GateRule::Peer(banned.peer_id()), None, None);
+        list.save().unwrap();
+        let mut config = test_config();
+        config.storage.ephemeral_peer_store = false;
+        config.storage.data_dir = Some(temp.path().to_path_buf());
+        let gated = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let gated_addr = tokio::time::timeout(Duration::from_secs(20), listen_addr(&gated)).await.unwrap();
+
+        let dial = tokio::time::timeout(Duration::from_secs(20), gated.dial(banned_addr)).await.unwrap();
+        assert!(dial.is_err(), "Dialing a banned peer must fail");
+
+        // A banned range is refused before the handshake, whoever is dialing in.
+        let mut list = GateList::open(&GateList::path(temp.path())).unwrap();
+        list.ban("127.0.0.0/8".parse().unwrap(), Some(Duration::from_secs(60)), None);
+        list.save().unwrap();
+        gated.shutdown().await.unwrap();
+        let mut config = test_config();
+        config.network.listen_addrs = vec![gated_addr.clone()];
+        config.storage.ephemeral_peer_store = false;
+        config.storage.data_dir = Some(temp.path().to_path_buf());
+        let gated = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        tokio::time::timeout(Duration::from_secs(20), listen_addr(&gated)).await.unwrap();
+
+        let stranger = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let dial = tokio::time::timeout(Duration::from_secs(20), stranger.dial(gated_addr)).await.unwrap();
+        assert!(dial.is_err(), "Connections from a banned range must be refused");
+    }
+
+    #[tokio::test]
+    async fn test_room_message_reaches_member_two_hops_away() {
+        use crate::network::behaviours::gate::{GateList, GateRule};
+
+        // Alice and Carol ban each other, so every message between them goes through Bob.
+        let (alice_identity, carol_identity) = (NodeIdentity::generate_ephemeral().unwrap(), NodeIdentity::generate_ephemeral().unwrap());
+        let (alice_dir, carol_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let line_config = |data_dir: &std::path::Path, banned: PeerId| {
+            let mut list = GateList::open(&GateList::path(data_dir)).unwrap();
+            list.ban(GateRule::Peer(banned), None, None);
+            list.save().unwrap();
+            let mut config = test_config();
+            config.rooms.announce_interval = Duration::from_secs(1);
+            config.storage.ephemeral_peer_store = false;
+            config.storage.data_dir = Some(data_dir.to_path_buf());
+            config
+        };
+        let alice_config = line_config(alice_dir.path(), carol_identity.peer_id());
+        let carol_config = line_config(carol_dir.path(), alice_identity.peer_id());
+        let alice = Node::spawn(alice_config, alice_identity).unwrap();
+        let carol = Node::spawn(carol_config, carol_identity).unwrap();
+        let mut bob_config = test_config();
+        bob_config.rooms.announce_interval = Duration::from_secs(1);
+        let bob = Node::spawn(bob_config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut carol_events = carol.subscribe();
+
+        let exchange = async {
+            let bob_addr = listen_addr(&bob).await;
+            alice.dial(bob_addr.clone()).await.unwrap();
+            carol.dial(bob_addr).await.unwrap();
+            for node in [&alice, &bob, &carol] {
+                node.join_room("general").await.unwrap();
+            }
+
+            // Carol's announcement has to be relayed by Bob before Alice seals for her.
+            while !alice.rooms().await.unwrap().iter().any(|room| room.members.contains(&carol.peer_id())) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            let peers = alice.peers().await.unwrap();
+            assert!(!peers.iter().any(|peer| peer.peer_id == carol.peer_id() && peer.connected));
+
+            alice.publish("general", "hello from two hops").await.unwrap();
+            loop {
+                if let NodeEvent::RoomMessage { sender, body, .. } = carol_events.recv().await.unwrap() {
+                    assert_eq!(sender, alice.peer_id());
+                    assert_eq!(body, "hello from two hops");
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(30), exchange).await.expect("Room message never reached Carol");
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit_is_enforced() {
+        let mut config = test_config();
+        config.limits.max_established_outgoing = Some(1);
+        let limited = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let first = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let second = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+
+        let exchange = async {
+            limited.dial(listen_addr(&first).await).await.unwrap();
+            assert!(limited.dial(listen_addr(&second).await).await.is_err(), "A second outbound connection is over the limit");
+        };
+        tokio::time::timeout(Duration::from_secs(20), exchange).await.expect("Dials timed out");
+    }
+
+    #[tokio::test]
+    async fn test_nodes_chat_over_quic() {
+        let quic_config = || {
+            let mut config = test_config();
+            config.network.listen_addrs = vec!["/ip4/127.0.0.1/udp/0/quic-v1".parse().unwrap()];
+            config
+        };
+        let alice = Node::spawn(quic_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let bob = Node::spawn(quic_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut alice_events = alice.subscribe();
+
+        let exchange = async {
+            let address = listen_addr(&alice).await;
+            assert!(crate::network::transport::quic::is_quic(&address));
+            bob.add_peer_address(alice.peer_id(), address).await.unwrap();
+
+            // No connection yet, so sending dials Alice's QUIC address.
+            let ack = bob.send(alice.peer_id(), "hello over quic").await.unwrap();
+            assert_eq!(ack.status, DeliveryStatus::Delivered);
+            loop {
+                if let NodeEvent::ChatMessage { body, .. } = alice_events.recv().await.unwrap() {
+                    assert_eq!(body, "hello over quic");
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(20), exchange).await.expect("QUIC exchange timed out");
+    }
+
+    #[tokio::test]
+    async fn test_nodes_chat_over_websocket_by_hostname() {
+        let mut config = test_config();
+        config.network.listen_addrs = vec!["/ip4/127.0.0.1/tcp/0/ws".parse().unwrap()];
+        let alice = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let bob = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut alice_events = alice.subscribe();
+
+        let exchange = async {
+            let address = listen_addr(&alice).await;
+            let port = address.iter().find_map(|protocol| match protocol {
+                libp2p::multiaddr::Protocol::Tcp(port) => Some(port),
+                _ => None,
+            }).unwrap();
+            // Resolved through DNS, then carried over WebSocket.
+            let address: Multiaddr = format!("/dns4/localhost/tcp/{port}/ws").parse().unwrap();
+            bob.dial(address.with(libp2p::multiaddr::Protocol::P2p(alice.peer_id()))).await.unwrap();
+
+            let ack = bob.send(alice.peer_id(), "hello over websocket").await.unwrap();
+            assert_eq!(ack.status, DeliveryStatus::Delivered);
+            loop {
+                if let NodeEvent::ChatMessage { body, .. } = alice_events.recv().await.unwrap() {
+                    assert_eq!(body, "hello over websocket");
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(20), exchange).await.expect("WebSocket exchange timed out");
+    }
+
+    #[tokio::test]
+    async fn test_nodes_chat_over_secure_websocket_by_hostname() {
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+        // A private CA that only vouches for `localhost`, so the dial fails unless TLS sees the name.
+        let temp = tempfile::tempdir().unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let key = KeyPair::generate().unwrap();
+        let certificate = CertificateParams::new(vec!["localhost".to_string()]).unwrap().signed_by(&key, &ca, &ca_key).unwrap();
+        let (ca_path, certificate_path, key_path) = (temp.path().join("ca.pem"), temp.path().join("cert.pem"), temp.path().join("key.pem"));
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+        std::fs::write(&certificate_path, certificate.pem()).unwrap();
+        std::fs::write(&key_path, key.serialize_pem()).unwrap();
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut config = test_config();
+        config.network.listen_addrs = vec![format!("/ip4/127.0.0.1/tcp/{port}/wss").parse().unwrap()];
+        config.websocket.tls_certificate = Some(certificate_path);
+        config.websocket.tls_private_key = Some(key_path);
+        let alice = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut config = test_config();
+        config.websocket.tls_trusted_certificates = vec![ca_path];
+        let bob = Node::spawn(config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut alice_events = alice.subscribe();
+
+        let exchange = async {
+            listen_addr(&alice).await;
+            let address: Multiaddr = format!("/dns4/localhost/tcp/{port}/wss/p2p/{}", alice.peer_id()).parse().unwrap();
+            bob.dial(address).await.unwrap();
+
+            let ack = bob.send(alice.peer_id(), "hello over secure websocket").await.unwrap();
+            assert_eq!(ack.status, DeliveryStatus::Delivered);
+            loop {
+                if let NodeEvent::ChatMessage { body, .. } = alice_events.recv().await.unwrap() {
+                    assert_eq!(body, "hello over secure websocket");
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(20), exchange).await.expect("Secure WebSocket exchange timed out");
+    }
+
+    #[tokio::test]
+    async fn test_relayed_chat_is_upgraded_to_direct() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let relay_addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{port}").parse().unwrap();
+        let mut relay_config = test_config();
+        relay_config.network.listen_addrs = vec![relay_addr.clone()];
+        relay_config.network.external_addrs = vec![relay_addr.clone()];
+        relay_config.relay.server.enabled = true;
+        let relay = Node::spawn(relay_config, NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let relay_addr = relay_addr.with(libp2p::multiaddr::Protocol::P2p(relay.peer_id()));
+
+        let mut bob_config = test_config();
+        bob_config.relay.relays = vec![relay_addr.clone()];
+        let bob_identity = NodeIdentity::generate_ephemeral().unwrap();
+        // Subscribe before the reservation can complete.
+        let (bob_node, bob) = Node::new(&bob_config, &bob_identity).unwrap();
+        let mut bob_events = bob.subscribe();
+        tokio::spawn(bob_node.run());
+        let alice = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut alice_events = alice.subscribe();
+
+        let exchange = async {
+            loop {
+                if let NodeEvent::RelayReserved { relay: reserved } = bob_events.recv().await.unwrap() {
+                    assert_eq!(reserved, relay.peer_id());
+                    break;
+                }
+            }
+            let circuit = circuit_listen_addr(&relay_addr).unwrap().with(libp2p::multiaddr::Protocol::P2p(bob.peer_id()));
+            alice.add_peer_address(bob.peer_id(), circuit).await.unwrap();
+
+            let ack = alice.send(bob.peer_id(), "hello through the relay").await.unwrap();
+            assert_eq!(ack.status, DeliveryStatus::Delivered);
+            loop {
+                if let NodeEvent::ChatMessage { body, .. } = bob_events.recv().await.unwrap() {
+                    assert_eq!(body, "hello through the relay");
+                    break;
+                }
+            }
+            // Both nodes are on loopback, so the relayed connection is upgraded to a direct one.
+            loop {
+                match alice_events.recv().await.unwrap() {
+                    NodeEvent::DirectConnectionUpgraded { peer } => {
+                        assert_eq!(peer, bob.peer_id());
+                        break;
+                    },
+                    NodeEvent::DirectConnectionFailed { error, .. } => panic!("Hole punching failed: {error}"),
+                    _ => {},
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(20), exchange).await.expect("Relayed exchange timed out");
+    }
+
+    #[tokio::test]
+    async fn test_reachable_node_becomes_dht_server() {
+        let autonat_config = || {
+            let mut config = test_config();
+            // Loopback peers are all we have here.
+            config.autonat.only_global_ips = false;
+            config.autonat.boot_delay = Duration::from_millis(500);
+            config.autonat.retry_interval = Duration::from_secs(1);
+            config.autonat.confidence_max = 0;
+            config
+        };
+        let (alice_node, alice) = Node::new(&autonat_config(), &NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        let mut alice_events = alice.subscribe();
+        tokio::spawn(alice_node.run());
+        let bob = Node::spawn(autonat_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+
+        let probe = async {
+            let address = listen_addr(&bob).await;
+            alice.dial(address).await.unwrap();
+            let (mut public, mut server) = (false, false);
+            while !(public && server) {
+                match alice_events.recv().await.unwrap() {
+                    NodeEvent::ReachabilityChanged { reachability } => public = matches!(reachability, Reachability::Public { .. }),
+                    NodeEvent::DhtModeChanged { server: serving } => server = serving,
+                    _ => {},
+                }
+            }
+            let info = alice.info().await.unwrap();
+            assert!(!info.external_addrs.is_empty());
+        };
+        tokio::time::timeout(Duration::from_secs(20), probe).await.expect("AutoNAT probe timed out");
+    }
+
+    #[test]
+    fn test_only_handshake_failures_count_against_peers() {
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+        let transport_error = |error: std::io::Error| DialError::Transport(vec![(address.clone(), TransportError::Other(error))]);
+
+        assert!(!is_handshake_failure(&transport_error(std::io::ErrorKind::ConnectionRefused.into())));
+        assert!(!is_handshake_failure(&DialError::NoAddresses));
+        assert!(is_handshake_failure(&transport_error(std::io::Error::other(noise::Error::AuthenticationFailed))));
+        assert!(is_handshake_failure(&transport_error(std::io::Error::other(NegotiationError::Failed))));
+        // A connection dropped mid-handshake is a network problem.
+        let dropped = noise::Error::Io(std::io::ErrorKind::ConnectionReset.into());
+        assert!(!is_handshake_failure(&transport_error(std::io::Error::other(dropped))));
+    }
+
+    #[tokio::test]
+    async fn test_dial_failure_is_reported() {
+        let node = Node::spawn(test_config(), NodeIdentity::generate_ephemeral().unwrap()).unwrap();
+        // Nothing listens on port 1.
+        let result = tokio::time::timeout(Duration::from_secs(20), node.dial("/ip4/127.0.0.1/tcp/1".parse().unwrap())).await.unwrap();
+        assert!(result.is_err());
+    }
+}
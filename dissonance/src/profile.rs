@@ -0,0 +1,140 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::{default_data_dir, CONTROL_SOCKET_FILE};
+use crate::NodeIdentity;
+
+/// The profile living directly in the data directory, as every node did before profiles existed.
+pub const DEFAULT_PROFILE: &str = "default";
+
+const PROFILES_DIR: &str = "profiles";
+
+/// A named identity with its own peer store and settings, kept in its own directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl Profile {
+    /// The profile called `name` under the default data directory.
+    pub fn named(name: &str) -> Result<Self> {
+        Self::in_root(&default_data_dir()?, name)
+    }
+
+    /// The profile called `name` under `root`; it does not have to exist yet.
+    pub fn in_root(root: &Path, name: &str) -> Result<Self> {
+        validate_name(name)?;
+        let dir = if name == DEFAULT_PROFILE {
+            root.to_path_buf()
+        } else {
+            root.join(PROFILES_DIR).join(name)
+        };
+        Ok(Profile { name: name.to_string(), dir })
+    }
+
+    /// The default profile followed by every named one, sorted by name.
+    pub fn list(root: &Path) -> Result<Vec<Self>> {
+        let mut profiles = vec![Self::in_root(root, DEFAULT_PROFILE)?];
+        let profiles_dir = root.join(PROFILES_DIR);
+        if !profiles_dir.exists() {
+            return Ok(profiles);
+        }
+        let mut named = Vec::new();
+        for entry in fs::read_dir(&profiles_dir).context("Failed to read profiles directory")? {
+            let entry = entry.context("Failed to read profiles directory")?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str()
+                && validate_name(name).is_ok() {
+                named.push(Self::in_root(root, name)?);
+            }
+        }
+        named.sort_by(|a, b| a.name.cmp(&b.name));
+        profiles.extend(named);
+        Ok(profiles)
+    }
+
+    pub fn exists(&self) -> bool {
+        self.identity_path().exists()
+    }
+
+    pub fn identity_path(&self) -> PathBuf {
+        NodeIdentity::identity_path(&self.dir)
+    }
+
+    /// Creates the profile directory and its identity, encrypted under `passphrase`.
+    pub fn create(&self, passphrase: &str) -> Result<NodeIdentity> {
+        if self.exists() {
+            bail!("Profile `{}` already exists", self.name);
+        }
+        fs::create_dir_all(&self.dir).context("Failed to create profile directory")?;
+        NodeIdentity::load_or_create(&self.identity_path(), passphrase)
+    }
+
+    /// Removes the profile directory with its identity and stores. The default profile holds the
+    /// other profiles, so it cannot be deleted this way.
+    pub fn delete(&self) -> Result<()> {
+        if self.name == DEFAULT_PROFILE {
+            bail!("The default profile cannot be deleted");
+        }
+        if !self.dir.exists() {
+            bail!("Profile `{}` does not exist", self.name);
+        }
+        if std::os::unix::net::UnixStream::connect(self.dir.join(CONTROL_SOCKET_FILE)).is_ok() {
+            bail!("Profile `{}` is in use by a running node", self.name);
+        }
+        fs::remove_dir_all(&self.dir).context("Failed to remove profile directory")
+    }
+}
+
+/// Profile names become directory names, so they are kept to a safe alphabet.
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        bail!("Invalid profile name `{name}`; use letters, digits, `-`, `_` and `.`");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_profiles_live_in_separate_directories() {
+        let temp = tempdir().unwrap();
+        let default = Profile::in_root(temp.path(), DEFAULT_PROFILE).unwrap();
+        assert_eq!(default.dir, temp.path());
+
+        let work = Profile::in_root(temp.path(), "work").unwrap();
+        let personal = Profile::in_root(temp.path(), "personal").unwrap();
+        let work_identity = work.create("hunter2").unwrap();
+        let personal_identity = personal.create("hunter2").unwrap();
+        assert_ne!(work_identity.peer_id(), personal_identity.peer_id());
+        assert!(work.create("hunter2").is_err(), "Creating twice should fail");
+
+        let names: Vec<_> = Profile::list(temp.path()).unwrap().into_iter().map(|profile| profile.name).collect();
+        assert_eq!(names, vec!["default", "personal", "work"]);
+
+        work.delete().unwrap();
+        assert!(!work.dir.exists());
+        assert!(personal.exists());
+        assert!(default.delete().is_err());
+    }
+
+    #[test]
+    fn test_rejects_unsafe_names() {
+        let temp = tempdir().unwrap();
+        for name in ["", "..", "../etc", "a/b", ".hidden"] {
+            assert!(Profile::in_root(temp.path(), name).is_err(), "{name:?} should be rejected");
+        }
+        assert!(Profile::in_root(temp.path(), "team-1.test_user").is_ok());
+    }
+}
@@ -0,0 +1,150 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::duration_secs;
+
+/// Scores are kept within ±`MAX_SCORE` so neither a long good history nor a burst of
+/// misbehaviour outweighs everything that comes after it.
+pub const MAX_SCORE: f64 = 100.0;
+
+/// Something a peer did that says how far it can be relied on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    /// The peer answered but failed the security or protocol negotiation, or its Identify exchange.
+    FailedHandshake,
+    /// The peer sent something no honest node would: a chat message it could not have sealed or
+    /// that claims another sender, or a succession certificate that does not check out.
+    ProtocolError,
+    /// The peer authored room messages that failed validation.
+    Spam,
+    /// The peer accepted and acknowledged one of our messages.
+    Delivered,
+    /// The peer stayed connected this long.
+    Uptime(Duration),
+}
+
+impl Signal {
+    pub fn weight(self) -> f64 {
+        match self {
+            Signal::FailedHandshake => -5.0,
+            Signal::ProtocolError => -20.0,
+            Signal::Spam => -10.0,
+            Signal::Delivered => 1.0,
+            // One point per ten minutes online.
+            Signal::Uptime(duration) => duration.as_secs_f64() / 600.0,
+        }
+    }
+}
+
+/// How a peer is treated given its score, from least to most restricted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Standing {
+    Good,
+    /// DHT records and provider announcements from the peer are not stored.
+    Suspect,
+    /// Kept out of the Kademlia routing table as well.
+    Evicted,
+    /// Connections to the peer are closed as soon as they open.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReputationConfig {
+    /// Time for a score to decay halfway back to zero.
+    #[serde(with = "duration_secs")]
+    pub half_life: Duration,
+    pub suspect_below: f64,
+    pub evict_below: f64,
+    pub disconnect_below: f64,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        ReputationConfig { half_life: Duration::from_secs(60 * 60), suspect_below: -20.0, evict_below: -40.0, disconnect_below: -60.0 }
+    }
+}
+
+impl ReputationConfig {
+    pub fn standing(&self, score: f64) -> Standing {
+        if score < self.disconnect_below {
+            Standing::Disconnected
+        } else if score < self.evict_below {
+            Standing::Evicted
+        } else if score < self.suspect_below {
+            Standing::Suspect
+        } else {
+            Standing::Good
+        }
+    }
+}
+
+/// A peer's accumulated score, decaying exponentially towards zero between signals.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Reputation {
+    score: f64,
+    updated: SystemTime,
+}
+
+impl Default for Reputation {
+    fn default() -> Self {
+        Reputation { score: 0.0, updated: UNIX_EPOCH }
+    }
+}
+
+impl Reputation {
+    /// The score as of `now`.
+    pub fn score(&self, half_life: Duration, now: SystemTime) -> f64 {
+        let elapsed = now.duration_since(self.updated).unwrap_or_default();
+        if half_life.is_zero() {
+            return 0.0;
+        }
+        self.score * 0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64())
+    }
+
+    /// Adds `signal` to the decayed score and returns the new score.
+    pub fn record(&mut self, signal: Signal, half_life: Duration, now: SystemTime) -> f64 {
+        self.score = (self.score(half_life, now) + signal.weight()).clamp(-MAX_SCORE, MAX_SCORE);
+        self.updated = now;
+        self.score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scores_accumulate_and_decay() {
+        let config = ReputationConfig::default();
+        let start = SystemTime::now();
+        let mut reputation = Reputation::default();
+
+        reputation.record(Signal::ProtocolError, config.half_life, start);
+        let score = reputation.record(Signal::ProtocolError, config.half_life, start);
+        assert_eq!(score, -40.0);
+        assert_eq!(config.standing(score), Standing::Suspect);
+        let score = reputation.record(Signal::Spam, config.half_life, start);
+        assert_eq!(config.standing(score), Standing::Evicted);
+
+        let later = start + config.half_life;
+        assert_eq!(reputation.score(config.half_life, later), -25.0);
+        assert_eq!(config.standing(reputation.score(config.half_life, later + config.half_life * 4)), Standing::Good);
+    }
+
+    #[test]
+    fn test_scores_are_bounded() {
+        let config = ReputationConfig::default();
+        let now = SystemTime::now();
+        let mut reputation = Reputation::default();
+        for _ in 0..10 {
+            reputation.record(Signal::ProtocolError, config.half_life, now);
+        }
+        assert_eq!(reputation.score(config.half_life, now), -MAX_SCORE);
+        assert_eq!(config.standing(-MAX_SCORE), Standing::Disconnected);
+        // Good behaviour still counts, so a peer can earn its way back.
+        assert!(reputation.record(Signal::Uptime(Duration::from_secs(3600)), config.half_life, now) > -MAX_SCORE);
+    }
+}
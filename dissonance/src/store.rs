@@ -1,6 +1,17 @@
-use std::{collections::HashMap, time::{Duration, SystemTime}};
+use std::{collections::HashMap, fs, io::Write, path::{Path, PathBuf}, time::{Duration, SystemTime}};
 
-use libp2p::{identify::Info, Multiaddr, PeerId, StreamProtocol};
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use libp2p::{identify::Info, multiaddr::Protocol, Multiaddr, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+
+use crate::config::default_data_dir;
+use crate::reputation::{Reputation, ReputationConfig, Signal, Standing};
+use crate::verification::ed25519_key;
+
+/// Bumped whenever `StoredPeerStore` changes shape. Files from older versions are read as they
+/// are, relying on `#[serde(default)]` for added fields; files from newer versions are refused.
+pub const PEER_STORE_SCHEMA_VERSION: u32 = 1;
 
 #[derive(Debug)]
 pub struct PeerInfo{
@@ -8,12 +19,15 @@ pub struct PeerInfo{
     pub addresses: Vec<Multiaddr>,
     pub agent_version: Option<String>,
     pub protocols: Vec<StreamProtocol>,
-    is_trusted: bool
+    is_trusted: bool,
+    /// The key whose safety number the user confirmed.
+    verified_key: Option<[u8; PUBLIC_KEY_LENGTH]>,
+    pub reputation: Reputation,
 }
 
 impl PeerInfo{
     pub fn new() -> Self{
-        PeerInfo { last_seen: SystemTime::now(), addresses: vec![], agent_version: None, protocols: vec![], is_trusted: false }
+        PeerInfo { last_seen: SystemTime::now(), addresses: vec![], agent_version: None, protocols: vec![], is_trusted: false, verified_key: None, reputation: Reputation::default() }
     }
 
     pub fn seen(&mut self){
@@ -33,19 +47,162 @@ impl PeerInfo{
     }
 }
 
+impl Default for PeerInfo{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredPeerInfo{
+    peer_id: PeerId,
+    last_seen: SystemTime,
+    addresses: Vec<Multiaddr>,
+    agent_version: Option<String>,
+    protocols: Vec<String>,
+    is_trusted: bool,
+    #[serde(default)]
+    verified_key: Option<[u8; PUBLIC_KEY_LENGTH]>,
+    #[serde(default)]
+    reputation: Reputation,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredAddressPin{
+    address: Multiaddr,
+    peer_id: PeerId,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredPeerStore{
+    schema_version: u32,
+    peers: Vec<StoredPeerInfo>,
+    #[serde(default)]
+    address_pins: Vec<StoredAddressPin>,
+}
+
+impl StoredPeerInfo{
+    fn from_peer_info(peer_id: PeerId, info: &PeerInfo) -> Self{
+        StoredPeerInfo {
+            peer_id,
+            last_seen: info.last_seen,
+            addresses: info.addresses.clone(),
+            agent_version: info.agent_version.clone(),
+            protocols: info.protocols.iter().map(|p| p.to_string()).collect(),
+            is_trusted: info.is_trusted,
+            verified_key: info.verified_key,
+            reputation: info.reputation,
+        }
+    }
+
+    fn into_peer_info(self) -> (PeerId, PeerInfo){
+        let info = PeerInfo {
+            last_seen: self.last_seen,
+            addresses: self.addresses,
+            agent_version: self.agent_version,
+            protocols: self.protocols.into_iter().filter_map(|p| StreamProtocol::try_from_owned(p).ok()).collect(),
+            is_trusted: self.is_trusted,
+            verified_key: self.verified_key,
+            reputation: self.reputation,
+        };
+        (self.peer_id, info)
+    }
+}
+
+
+/// What changed when a known peer handed over to a new identity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Succession{
+    /// The user had verified the retired key, so the new one needs verifying again.
+    pub was_verified: bool,
+}
+
+/// Outcome of comparing what a peer presents with what it presented on first contact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinCheck<T>{
+    /// Nothing was pinned yet; the presented value is now.
+    Pinned,
+    Matched,
+    /// Differs from the pin, which is left in place.
+    Mismatched(T),
+}
 
 #[derive(Debug, Default)]
 pub struct PeerStore{
     known_peers: HashMap<PeerId, PeerInfo>,
+    /// The peer that answered the first time we dialed each address.
+    address_pins: HashMap<Multiaddr, PeerId>,
+    path: Option<PathBuf>,
+    dirty: bool,
 }
 
 impl PeerStore {
     pub fn new() -> Self{
-        PeerStore { known_peers: HashMap::new() }
+        PeerStore { known_peers: HashMap::new(), address_pins: HashMap::new(), path: None, dirty: false }
+    }
+
+    /// Opens the peer store kept next to the node identity, creating it on first use.
+    pub fn get_store() -> Result<Self>{
+        Self::open(&Self::store_path(&default_data_dir()?))
+    }
+
+    pub fn store_path(data_dir: &Path) -> PathBuf{
+        data_dir.join("peer-store.json")
+    }
+
+    pub fn open(path: &Path) -> Result<Self>{
+        let mut store = if path.exists(){
+            Self::load_from_file(path)?
+        }else{
+            Self::new()
+        };
+        store.path = Some(path.to_path_buf());
+        tracing::info!("Loaded {} known peers from {}", store.known_peers.len(), path.display());
+        Ok(store)
+    }
+
+    fn load_from_file(path: &Path) -> Result<Self>{
+        let content = fs::read_to_string(path).context("Failed to read peer store")?;
+        let stored: StoredPeerStore = serde_json::from_str(&content).context("Failed to parse peer store")?;
+
+        if stored.schema_version > PEER_STORE_SCHEMA_VERSION{
+            bail!("Peer store schema version {} is newer than supported version {}", stored.schema_version, PEER_STORE_SCHEMA_VERSION);
+        }
+
+        let known_peers = stored.peers.into_iter().map(StoredPeerInfo::into_peer_info).collect();
+        let address_pins = stored.address_pins.into_iter().map(|pin| (pin.address, pin.peer_id)).collect();
+        Ok(PeerStore { known_peers, address_pins, path: None, dirty: false })
+    }
+
+    /// Writes the store to disk if anything changed since the last flush.
+    pub fn flush(&mut self) -> Result<()>{
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty{
+            return Ok(());
+        }
+
+        let stored = StoredPeerStore {
+            schema_version: PEER_STORE_SCHEMA_VERSION,
+            peers: self.known_peers.iter().map(|(peer_id, info)| StoredPeerInfo::from_peer_info(*peer_id, info)).collect(),
+            address_pins: self.address_pins.iter().map(|(address, peer_id)| StoredAddressPin { address: address.clone(), peer_id: *peer_id }).collect(),
+        };
+        let content = serde_json::to_vec_pretty(&stored).context("Failed to serialize peer store")?;
+        write_atomic(path, &content).context("Failed to write peer store")?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn is_dirty(&self) -> bool{
+        self.dirty
     }
 
     pub fn get_or_create(&mut self, peer_id: &PeerId) -> &mut PeerInfo{
-        self.known_peers.entry(peer_id.clone()).or_insert_with(PeerInfo::new)
+        // Callers mutate through the returned reference, so assume the entry changed.
+        self.dirty = true;
+        self.known_peers.entry(*peer_id).or_default()
     }
 
     pub fn add_peer_address(&mut self, peer_id: &PeerId, address: Multiaddr){
@@ -63,18 +220,310 @@ impl PeerStore {
         peer_info.is_trusted
     }
 
-    pub fn list_peers(&mut self) -> Vec<(&PeerId, &PeerInfo)>{
+    /// Records that the user compared safety numbers with `peer_id` and trusts its current key.
+    pub fn mark_verified(&mut self, peer_id: &PeerId) -> Result<()>{
+        let key = ed25519_key(peer_id).context("Only peers with ed25519 keys can be verified")?;
+        let info = self.get_or_create(peer_id);
+        info.verified_key = Some(key);
+        info.is_trusted = true;
+        Ok(())
+    }
+
+    pub fn clear_verified(&mut self, peer_id: &PeerId){
+        if let Some(info) = self.known_peers.get_mut(peer_id){
+            info.verified_key = None;
+            info.is_trusted = false;
+            self.dirty = true;
+        }
+    }
+
+    /// Whether the user verified the key `peer_id` currently has.
+    pub fn is_verified(&self, peer_id: &PeerId) -> bool{
+        self.known_peers.get(peer_id)
+            .and_then(|info| info.verified_key)
+            .is_some_and(|key| ed25519_key(peer_id) == Some(key))
+    }
+
+    /// Pins `peer_id` to the dialed `address` the first time it answers there and compares against
+    /// the pin afterwards. Returns the pinned peer on a mismatch.
+    /// The peer that first answered at `address`, if we ever dialed it.
+    pub fn pinned_peer(&self, address: &Multiaddr) -> Option<PeerId>{
+        self.address_pins.get(&without_peer_id(address)).copied()
+    }
+
+    pub fn check_address_pin(&mut self, address: &Multiaddr, peer_id: &PeerId) -> PinCheck<PeerId>{
+        let address = without_peer_id(address);
+        match self.address_pins.get(&address){
+            Some(pinned) if pinned == peer_id => PinCheck::Matched,
+            Some(pinned) => PinCheck::Mismatched(*pinned),
+            None => {
+                self.address_pins.insert(address, *peer_id);
+                self.dirty = true;
+                PinCheck::Pinned
+            },
+        }
+    }
+
+    /// Forgets which peer answered at `address`, e.g. after it legitimately changed hands.
+    /// Returns the peer it was pinned to.
+    pub fn unpin_address(&mut self, address: &Multiaddr) -> Option<PeerId>{
+        let removed = self.address_pins.remove(&without_peer_id(address));
+        self.dirty |= removed.is_some();
+        removed
+    }
+
+    /// Adds `signal` to the peer's reputation and returns how it should be treated now.
+    pub fn record_signal(&mut self, peer_id: &PeerId, signal: Signal, config: &ReputationConfig) -> Standing{
+        let score = self.get_or_create(peer_id).reputation.record(signal, config.half_life, SystemTime::now());
+        config.standing(score)
+    }
+
+    /// The peer's current score; unknown peers start at zero.
+    pub fn score(&self, peer_id: &PeerId, config: &ReputationConfig) -> f64{
+        self.known_peers.get(peer_id).map_or(0.0, |info| info.reputation.score(config.half_life, SystemTime::now()))
+    }
+
+    pub fn standing(&self, peer_id: &PeerId, config: &ReputationConfig) -> Standing{
+        config.standing(self.score(peer_id, config))
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<&PeerInfo>{
+        self.known_peers.get(peer_id)
+    }
+
+    pub fn list_peers(&self) -> Vec<(&PeerId, &PeerInfo)>{
         self.known_peers.iter().collect()
     }
 
+    /// Moves what we know about `previous`, including whether we trust it, over to the identity
+    /// that replaced it. Verification belongs to a key, so it does not carry over.
+    pub fn apply_succession(&mut self, previous: &PeerId, successor: &PeerId) -> Option<Succession>{
+        let old = self.known_peers.remove(previous)?;
+        let was_verified = old.verified_key.is_some_and(|key| ed25519_key(previous) == Some(key));
+        let info = self.get_or_create(successor);
+        info.is_trusted |= old.is_trusted;
+        for address in old.addresses{
+            if !info.addresses.contains(&address){
+                info.addresses.push(address);
+            }
+        }
+        if info.agent_version.is_none(){
+            info.agent_version = old.agent_version;
+            info.protocols = old.protocols;
+        }
+        info.last_seen = info.last_seen.max(old.last_seen);
+        if info.reputation == Reputation::default(){
+            info.reputation = old.reputation;
+        }
+        // The successor answers where the retired identity used to.
+        for pinned in self.address_pins.values_mut().filter(|pinned| *pinned == previous){
+            *pinned = *successor;
+        }
+        Some(Succession { was_verified })
+    }
+
     pub fn insert_peer_info(&mut self, peer_id: PeerId, info: PeerInfo) {
         self.known_peers.insert(peer_id, info);
+        self.dirty = true;
     }
 
-    pub fn prune_stale(&mut self, max_age: Duration){
+    /// Forgets peers not seen within `max_age` and returns how many were removed.
+    pub fn prune_stale(&mut self, max_age: Duration) -> usize{
         let now = SystemTime::now();
+        let before = self.known_peers.len();
         self.known_peers.retain(|_, info|{
             now.duration_since(info.last_seen).map(|age| age<max_age).unwrap_or(false)
         });
+        self.dirty |= self.known_peers.len() != before;
+        // Pins to forgotten peers would otherwise pile up forever.
+        let pins = self.address_pins.len();
+        self.address_pins.retain(|_, pinned| self.known_peers.contains_key(pinned));
+        self.dirty |= self.address_pins.len() != pins;
+        before - self.known_peers.len()
+    }
+}
+
+/// Pins are kept per transport address; a trailing `/p2p/<peer>` is what is being checked.
+fn without_peer_id(address: &Multiaddr) -> Multiaddr{
+    let mut address = address.clone();
+    if let Some(Protocol::P2p(_)) = address.iter().last(){
+        address.pop();
+    }
+    address
+}
+
+/// Writes to a sibling temp file and renames it into place so a crash mid-write never truncates `path`.
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> Result<()>{
+    let parent = path.parent().context("Path has no parent directory")?;
+    fs::create_dir_all(parent).context("Failed to create parent directory")?;
+
+    let mut file = tempfile::NamedTempFile::new_in(parent).context("Failed to create temporary file")?;
+    file.write_all(content).context("Failed to write temporary file")?;
+    file.persist(path).context("Failed to replace file")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_flush_and_reload_peer_store() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("peer-store.json");
+        let peer_id = PeerId::random();
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+
+        let mut store = PeerStore::open(&path).expect("Failed to open peer store");
+        store.add_peer_address(&peer_id, address.clone());
+        store.get_or_create(&peer_id).protocols = vec![StreamProtocol::new("/dissonance/chat/1.0.0")];
+        assert!(store.is_dirty());
+        store.flush().expect("Failed to flush peer store");
+        assert!(!store.is_dirty());
+
+        let reloaded = PeerStore::open(&path).expect("Failed to reload peer store");
+        let peers = reloaded.list_peers();
+        assert_eq!(peers.len(), 1);
+        let (reloaded_id, info) = peers[0];
+        assert_eq!(*reloaded_id, peer_id);
+        assert_eq!(info.addresses, vec![address]);
+        assert_eq!(info.protocols, vec![StreamProtocol::new("/dissonance/chat/1.0.0")]);
+    }
+
+    #[test]
+    fn test_rejects_newer_schema_version() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("peer-store.json");
+        fs::write(&path, format!(r#"{{"schema_version": {}, "peers": []}}"#, PEER_STORE_SCHEMA_VERSION + 1)).unwrap();
+
+        assert!(PeerStore::open(&path).is_err());
+    }
+
+    #[test]
+    fn test_in_memory_store_never_writes() {
+        let mut store = PeerStore::new();
+        store.add_peer_address(&PeerId::random(), "/ip4/127.0.0.1/tcp/4001".parse().unwrap());
+        store.flush().expect("Flushing an in-memory store is a no-op");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_prune_stale_marks_dirty() {
+        let temp = tempdir().unwrap();
+        let mut store = PeerStore::open(&temp.path().join("peer-store.json")).unwrap();
+        let stale = PeerId::random();
+        store.get_or_create(&stale).last_seen = SystemTime::now() - Duration::from_secs(3600);
+        store.get_or_create(&PeerId::random());
+        store.flush().unwrap();
+
+        store.prune_stale(Duration::from_secs(60));
+        assert!(store.is_dirty());
+        assert_eq!(store.list_peers().len(), 1);
+    }
+
+    #[test]
+    fn test_succession_moves_trust_to_successor() {
+        let mut store = PeerStore::new();
+        let (previous, successor) = (PeerId::random(), PeerId::random());
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        store.add_peer_address(&previous, address.clone());
+        store.get_or_create(&previous).is_trusted = true;
+
+        assert_eq!(store.apply_succession(&previous, &successor), Some(Succession { was_verified: false }));
+        assert!(store.get(&previous).is_none());
+        assert!(store.is_peer_trusted(&successor));
+        assert_eq!(store.get(&successor).unwrap().addresses, vec![address]);
+        assert!(store.apply_succession(&previous, &successor).is_none(), "Only known peers can be succeeded");
+    }
+
+    #[test]
+    fn test_verification_is_tied_to_the_key() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("peer-store.json");
+        let contact = crate::NodeIdentity::generate_ephemeral().unwrap().peer_id();
+        let successor = crate::NodeIdentity::generate_ephemeral().unwrap().peer_id();
+
+        let mut store = PeerStore::open(&path).unwrap();
+        assert!(store.mark_verified(&PeerId::random()).is_err());
+        store.mark_verified(&contact).unwrap();
+        store.flush().unwrap();
+
+        let mut store = PeerStore::open(&path).unwrap();
+        assert!(store.is_verified(&contact), "Verification should survive a reload");
+
+        assert_eq!(store.apply_succession(&contact, &successor), Some(Succession { was_verified: true }));
+        assert!(!store.is_verified(&successor), "A new key has to be verified again");
+        assert!(store.is_peer_trusted(&successor));
+
+        store.mark_verified(&successor).unwrap();
+        store.clear_verified(&successor);
+        assert!(!store.is_verified(&successor));
+    }
+
+    #[test]
+    fn test_reputation_survives_reload() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("peer-store.json");
+        let config = ReputationConfig::default();
+        let peer_id = PeerId::random();
+
+        let mut store = PeerStore::open(&path).unwrap();
+        assert_eq!(store.standing(&peer_id, &config), Standing::Good);
+        store.record_signal(&peer_id, Signal::ProtocolError, &config);
+        assert_eq!(store.record_signal(&peer_id, Signal::Spam, &config), Standing::Suspect);
+        store.flush().unwrap();
+
+        let store = PeerStore::open(&path).unwrap();
+        assert!(store.score(&peer_id, &config) < config.suspect_below);
+        assert_eq!(store.standing(&peer_id, &config), Standing::Suspect);
+    }
+
+    #[test]
+    fn test_first_answering_peer_is_pinned() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("peer-store.json");
+        let contact = crate::NodeIdentity::generate_ephemeral().unwrap();
+        let impostor = crate::NodeIdentity::generate_ephemeral().unwrap();
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+
+        let mut store = PeerStore::open(&path).unwrap();
+        assert_eq!(store.check_address_pin(&address, &contact.peer_id()), PinCheck::Pinned);
+        store.add_peer_address(&contact.peer_id(), address.clone());
+        store.flush().unwrap();
+
+        let mut store = PeerStore::open(&path).unwrap();
+        let with_peer = address.clone().with(Protocol::P2p(contact.peer_id()));
+        assert_eq!(store.check_address_pin(&with_peer, &contact.peer_id()), PinCheck::Matched);
+        assert_eq!(store.check_address_pin(&address, &impostor.peer_id()), PinCheck::Mismatched(contact.peer_id()));
+
+        assert_eq!(store.unpin_address(&address), Some(contact.peer_id()));
+        assert_eq!(store.check_address_pin(&address, &impostor.peer_id()), PinCheck::Pinned);
+    }
+
+    #[test]
+    fn test_pruning_drops_pins_to_forgotten_peers() {
+        let mut store = PeerStore::new();
+        let (stale, fresh) = (PeerId::random(), PeerId::random());
+        let (stale_address, fresh_address): (Multiaddr, Multiaddr) = ("/ip4/10.0.0.1/tcp/4001".parse().unwrap(), "/ip4/10.0.0.2/tcp/4001".parse().unwrap());
+        store.check_address_pin(&stale_address, &stale);
+        store.check_address_pin(&fresh_address, &fresh);
+        store.add_peer_address(&fresh, fresh_address.clone());
+        store.get_or_create(&stale).last_seen = SystemTime::now() - Duration::from_secs(3600);
+
+        store.prune_stale(Duration::from_secs(60));
+        assert_eq!(store.unpin_address(&stale_address), None);
+        assert_eq!(store.unpin_address(&fresh_address), Some(fresh));
+    }
+
+    #[test]
+    fn test_succession_moves_address_pins() {
+        let mut store = PeerStore::new();
+        let (previous, successor) = (PeerId::random(), PeerId::random());
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        store.check_address_pin(&address, &previous);
+        store.add_peer_address(&previous, address.clone());
+
+        store.apply_succession(&previous, &successor).unwrap();
+        assert_eq!(store.check_address_pin(&address, &successor), PinCheck::Matched);
+    }
+}
@@ -0,0 +1,70 @@
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use libp2p::PeerId;
+use sha2::{Digest, Sha512};
+
+use crate::e2e::peer_ed25519_key;
+
+const SAFETY_NUMBER_CONTEXT: &[u8] = b"dissonance-safety-number-v1";
+const SAFETY_NUMBER_GROUPS: usize = 12;
+const GROUP_BYTES: usize = 5;
+
+/// Numeric code two contacts compare out of band, e.g. read aloud or side by side. Both parties
+/// derive the same 60 digits from their two public keys, so a man in the middle shows up as a
+/// mismatch.
+pub fn safety_number(ours: &[u8; PUBLIC_KEY_LENGTH], theirs: &[u8; PUBLIC_KEY_LENGTH]) -> String {
+    let (first, second) = if ours <= theirs { (ours, theirs) } else { (theirs, ours) };
+    let digest = Sha512::new()
+        .chain_update(SAFETY_NUMBER_CONTEXT)
+        .chain_update(first)
+        .chain_update(second)
+        .finalize();
+
+    digest
+        .chunks(GROUP_BYTES)
+        .take(SAFETY_NUMBER_GROUPS)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+            format!("{:05}", value % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The ed25519 key a peer id was derived from. Ed25519 keys are short enough to be inlined in
+/// the peer id, so no lookup is needed.
+pub fn ed25519_key(peer: &PeerId) -> Option<[u8; PUBLIC_KEY_LENGTH]> {
+    peer_ed25519_key(peer).ok()
+}
+
+/// `safety_number` for a pair of peers, if both use ed25519 keys.
+pub fn safety_number_for(ours: &PeerId, theirs: &PeerId) -> Option<String> {
+    Some(safety_number(&ed25519_key(ours)?, &ed25519_key(theirs)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NodeIdentity;
+
+    #[test]
+    fn test_both_sides_see_the_same_number() {
+        let alice = NodeIdentity::generate_ephemeral().unwrap();
+        let bob = NodeIdentity::generate_ephemeral().unwrap();
+        let mallory = NodeIdentity::generate_ephemeral().unwrap();
+
+        let from_alice = safety_number(&alice.pub_key_bytes(), &bob.pub_key_bytes());
+        let from_bob = safety_number(&bob.pub_key_bytes(), &alice.pub_key_bytes());
+        assert_eq!(from_alice, from_bob);
+        assert_eq!(from_alice.len(), SAFETY_NUMBER_GROUPS * 6 - 1);
+        assert!(from_alice.split(' ').all(|group| group.len() == 5 && group.chars().all(|c| c.is_ascii_digit())));
+
+        assert_ne!(from_alice, safety_number(&alice.pub_key_bytes(), &mallory.pub_key_bytes()));
+    }
+
+    #[test]
+    fn test_key_is_recovered_from_peer_id() {
+        let identity = NodeIdentity::generate_ephemeral().unwrap();
+        assert_eq!(ed25519_key(&identity.peer_id()), Some(identity.pub_key_bytes()));
+        assert!(safety_number_for(&identity.peer_id(), &PeerId::random()).is_none(), "Random peer ids carry no key");
+    }
+}